@@ -0,0 +1,36 @@
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use gen_inds::vec_based::GenIndexAllocator;
+
+const NUM_VALUES: usize = 10_000;
+
+fn allocate_all(mut gen_alloc: GenIndexAllocator<usize>) -> GenIndexAllocator<usize> {
+    for value in 0..NUM_VALUES {
+        black_box(gen_alloc.allocate(value).expect("Should allocate"));
+    }
+    gen_alloc
+}
+
+fn bench_allocate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("allocate");
+
+    group.bench_function("with_capacity", |b| {
+        b.iter_batched(
+            || GenIndexAllocator::with_capacity(NUM_VALUES),
+            allocate_all,
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.bench_function("new_with_first_free", |b| {
+        b.iter_batched(
+            || GenIndexAllocator::new_with_first_free(NUM_VALUES),
+            allocate_all,
+            BatchSize::SmallInput,
+        )
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_allocate);
+criterion_main!(benches);
@@ -0,0 +1,39 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use gen_inds::vec_based::{GenIndex, GenIndexAllocator};
+
+const NUM_VALUES: usize = 100_000;
+
+/// Allocator with every other value deallocated, and keys for all slots of which half are stale
+fn setup<T>(value: impl Fn(usize) -> T) -> (GenIndexAllocator<T>, Vec<GenIndex>) {
+    let mut gen_alloc = GenIndexAllocator::with_capacity(NUM_VALUES);
+    let keys: Vec<_> = (0..NUM_VALUES)
+        .map(|index| gen_alloc.allocate(value(index)).expect("Should allocate"))
+        .collect();
+    for key in keys.iter().step_by(2) {
+        gen_alloc.deallocate(key).expect("Should deallocate");
+    }
+    (gen_alloc, keys)
+}
+
+fn count_valid<T>(gen_alloc: &GenIndexAllocator<T>, keys: &[GenIndex]) -> usize {
+    keys.iter().filter(|key| gen_alloc.contains(key)).count()
+}
+
+fn bench_validate(c: &mut Criterion) {
+    let mut group = c.benchmark_group("validate");
+
+    let (small, small_keys) = setup(|index| index as u32);
+    group.bench_function("small_values", |b| {
+        b.iter(|| count_valid(black_box(&small), black_box(&small_keys)))
+    });
+
+    let (large, large_keys) = setup(|index| [index as u8; 256]);
+    group.bench_function("large_values", |b| {
+        b.iter(|| count_valid(black_box(&large), black_box(&large_keys)))
+    });
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_validate);
+criterion_main!(benches);
@@ -1,3 +1,4 @@
+pub mod secondary_map;
 pub mod vec_based;
 
 pub type Error = Box<dyn std::error::Error>;
@@ -0,0 +1,201 @@
+use crate::vec_based::{GenIndex, SlotObserver};
+
+#[derive(Debug)]
+struct SecondaryEntry<T> {
+    generation: u32,
+    value: T,
+}
+
+/// Map associating additional values with keys of a primary `GenIndexAllocator`
+///
+/// Entries are stored by the index of the key, so lookups are O(1). An entry is only returned for
+/// a key with the exact generation it was inserted with. Register the map with
+/// `GenIndexAllocator::set_slot_observer`, shared as `Arc<Mutex<_>>`, to drop stale entries
+/// eagerly.
+#[derive(Debug)]
+pub struct SecondaryMap<T> {
+    entries: Vec<Option<SecondaryEntry<T>>>,
+    num_occupied: usize,
+}
+
+impl<T> SecondaryMap<T> {
+    pub fn new() -> Self {
+        Self::with_capacity(100)
+    }
+
+    pub fn with_capacity(capacity: usize) -> Self {
+        Self {
+            entries: Vec::with_capacity(capacity),
+            num_occupied: 0,
+        }
+    }
+
+    /// Insert `value` for `key`, returning the previous value for the same key
+    ///
+    /// An entry for an older generation of the same slot is replaced and dropped.
+    pub fn insert(&mut self, key: GenIndex, value: T) -> Option<T> {
+        let index = key.index();
+        if index >= self.entries.len() {
+            self.entries.resize_with(index + 1, || None);
+        }
+
+        let new_entry = SecondaryEntry {
+            generation: key.generation(),
+            value,
+        };
+        match self.entries[index].replace(new_entry) {
+            None => {
+                self.num_occupied += 1;
+                None
+            }
+            Some(old_entry) if old_entry.generation == key.generation() => Some(old_entry.value),
+            Some(_) => None,
+        }
+    }
+
+    pub fn get(&self, key: &GenIndex) -> Option<&T> {
+        match self.entries.get(key.index()) {
+            Some(Some(entry)) if entry.generation == key.generation() => Some(&entry.value),
+            _ => None,
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &GenIndex) -> Option<&mut T> {
+        match self.entries.get_mut(key.index()) {
+            Some(Some(entry)) if entry.generation == key.generation() => Some(&mut entry.value),
+            _ => None,
+        }
+    }
+
+    pub fn remove(&mut self, key: &GenIndex) -> Option<T> {
+        let slot = self.entries.get_mut(key.index())?;
+        match slot {
+            Some(entry) if entry.generation == key.generation() => {
+                self.num_occupied -= 1;
+                slot.take().map(|entry| entry.value)
+            }
+            _ => None,
+        }
+    }
+
+    pub fn contains_key(&self, key: &GenIndex) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_occupied
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<T> Default for SecondaryMap<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T> SlotObserver for SecondaryMap<T> {
+    fn on_free(&mut self, index: usize) {
+        if let Some(slot) = self.entries.get_mut(index) {
+            if slot.take().is_some() {
+                self.num_occupied -= 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::vec_based::GenIndexAllocator;
+    use crate::Error;
+    use std::sync::{Arc, Mutex};
+
+    #[test]
+    fn test_insert_and_get() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let mut secondary = SecondaryMap::with_capacity(10);
+
+        let key1 = gen_alloc.allocate(1)?;
+        let key2 = gen_alloc.allocate(2)?;
+        assert_eq!(secondary.insert(key1, "one"), None);
+        assert_eq!(secondary.insert(key2, "two"), None);
+        assert_eq!(secondary.insert(key2, "zwei"), Some("two"));
+        assert_eq!(secondary.len(), 2);
+        assert_eq!(secondary.get(&key1), Some(&"one"));
+        assert_eq!(secondary.get(&key2), Some(&"zwei"));
+
+        // A key for a reused slot should not see the value of the old generation
+        gen_alloc.deallocate(&key1)?;
+        let key3 = gen_alloc.allocate(3)?;
+        assert_eq!(key3.index(), key1.index());
+        assert_eq!(secondary.get(&key3), None);
+
+        assert_eq!(secondary.remove(&key2), Some("zwei"));
+        assert!(!secondary.contains_key(&key2));
+        assert_eq!(secondary.len(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_observer_removes_freed_entries() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let secondary = Arc::new(Mutex::new(SecondaryMap::with_capacity(10)));
+        gen_alloc.set_slot_observer(secondary.clone());
+
+        let keys: Vec<_> = (0..8)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        for key in keys.iter() {
+            secondary.lock().unwrap().insert(*key, *key);
+        }
+        let contains = |key: &GenIndex| secondary.lock().unwrap().contains_key(key);
+
+        gen_alloc.deallocate(&keys[0])?;
+        assert!(!contains(&keys[0]));
+
+        gen_alloc.retain(|_, value| *value != 1);
+        assert!(!contains(&keys[1]));
+
+        gen_alloc.retain_with(|_, value| *value != 2, |_, _| {});
+        assert!(!contains(&keys[2]));
+
+        gen_alloc.clear_range(3..4);
+        assert!(!contains(&keys[3]));
+
+        // The value at index 4 is replaced by the last one, both old keys become stale
+        gen_alloc.swap_remove_index(keys[4].index());
+        assert!(!contains(&keys[4]));
+        assert!(!contains(&keys[7]));
+        assert_eq!(secondary.lock().unwrap().len(), 2);
+
+        // Compaction moves the remaining values to the freed front slots
+        gen_alloc.compact();
+        assert!(!contains(&keys[5]));
+        assert!(!contains(&keys[6]));
+        assert!(secondary.lock().unwrap().is_empty());
+
+        let key = gen_alloc.allocate(8)?;
+        secondary.lock().unwrap().insert(key, key);
+        gen_alloc.drain_to_vec();
+        assert!(!contains(&key));
+
+        let key = gen_alloc.allocate(9)?;
+        secondary.lock().unwrap().insert(key, key);
+        gen_alloc.clear();
+        assert!(!contains(&key));
+
+        // Without an observer the map keeps its entries until they are looked up
+        gen_alloc.remove_slot_observer();
+        let key = gen_alloc.allocate(10)?;
+        secondary.lock().unwrap().insert(key, key);
+        gen_alloc.deallocate(&key)?;
+        assert_eq!(secondary.lock().unwrap().len(), 1);
+
+        Ok(())
+    }
+}
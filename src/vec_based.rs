@@ -1,21 +1,248 @@
+use crate::secondary_map::SecondaryMap;
 use crate::Error;
 use simple_error::bail;
+use std::collections::{HashMap, HashSet, TryReserveError, VecDeque};
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct GenIndex {
     index: usize,
     generation: u32,
 }
 
-#[derive(Debug)]
-struct GenIndexEntry<T> {
-    key: GenIndex,
-    value: Option<T>,
+impl GenIndex {
+    /// Build a key from its parts
+    ///
+    /// Keys should normally come from the allocator. A key built from a wrong generation can
+    /// refer to a different value than intended.
+    pub fn new(index: usize, generation: u32) -> Self {
+        Self { index, generation }
+    }
+
+    pub fn index(&self) -> usize {
+        self.index
+    }
+
+    pub fn generation(&self) -> u32 {
+        self.generation
+    }
+
+    /// Pack the key into a `u64` with the generation in the high and the index in the low 32 bits
+    ///
+    /// Panics if the index does not fit into 32 bits, see `GenIndexAllocator::with_index_hint`.
+    pub fn to_bits(&self) -> u64 {
+        let index = u32::try_from(self.index).expect("GenIndex::to_bits: Index exceeds 32 bits");
+        (u64::from(self.generation) << 32) | u64::from(index)
+    }
+
+    /// Unpack a key packed with `to_bits`
+    pub fn from_bits(bits: u64) -> Self {
+        Self {
+            index: (bits & u64::from(u32::MAX)) as usize,
+            generation: (bits >> 32) as u32,
+        }
+    }
+}
+
+/// Generation and liveness of a slot
+///
+/// The metadata of all slots is kept in its own array, apart from the values. Validating a key
+/// then only reads this compact array, however large the values are (see the `validate`
+/// benchmark).
+#[derive(Clone, Copy, Debug)]
+struct Slot {
+    generation: u32,
+    /// Whether the slot holds a live value, independent of what the value looks like
+    occupied: bool,
+}
+
+/// Receives notifications about slots freed in a `GenIndexAllocator`
+///
+/// Register an observer with `GenIndexAllocator::set_slot_observer` to keep dependent structures
+/// such as a `SecondaryMap` in sync eagerly.
+pub trait SlotObserver {
+    fn on_free(&mut self, index: usize);
+}
+
+/// Shared observer, e.g. a `SecondaryMap` that is also used outside of the allocator
+impl<O: SlotObserver> SlotObserver for Arc<Mutex<O>> {
+    fn on_free(&mut self, index: usize) {
+        self.lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+            .on_free(index);
+    }
+}
+
+/// What to do when the generation of a slot cannot be incremented any further
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum OverflowPolicy {
+    /// Panic when freeing the slot, except when dropping a `ReservedSlot`, which retires it
+    Panic,
+    /// Restart at generation 0, which can make very old keys valid again
+    Wrap,
+    /// Fail `deallocate` and keep the value, retire the slot in methods that cannot fail
+    Error,
+    /// Free the value but never reuse the slot
+    #[default]
+    Retire,
+}
+
+/// Error when requesting mutable access to several entries at once
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum GetManyError {
+    /// The key is stale or out of range
+    InvalidKey(GenIndex),
+    /// The slot index is requested more than once
+    DuplicateIndex(usize),
+}
+
+impl std::fmt::Display for GetManyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            GetManyError::InvalidKey(key) => write!(f, "Invalid key {:?}", key),
+            GetManyError::DuplicateIndex(index) => {
+                write!(f, "Index {} is requested more than once", index)
+            }
+        }
+    }
+}
+
+impl std::error::Error for GetManyError {}
+
+/// Error when requesting mutable access to two entries with `get_mut_pair`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PairError {
+    /// Both keys refer to the same entry
+    SameEntity,
+    /// The first key is stale or out of range
+    FirstInvalid,
+    /// The second key is stale or out of range
+    SecondInvalid,
+}
+
+impl std::fmt::Display for PairError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PairError::SameEntity => write!(f, "Both keys refer to the same entry"),
+            PairError::FirstInvalid => write!(f, "First key is invalid"),
+            PairError::SecondInvalid => write!(f, "Second key is invalid"),
+        }
+    }
+}
+
+impl std::error::Error for PairError {}
+
+/// Error when `allocate` would need a slot index above the cap set with `with_index_hint`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct IndexTooLarge {
+    pub index: usize,
+    pub max_index: usize,
+}
+
+impl std::fmt::Display for IndexTooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Index {} exceeds the maximum index {}",
+            self.index, self.max_index
+        )
+    }
+}
+
+impl std::error::Error for IndexTooLarge {}
+
+/// Snapshot of the memory usage of a `GenIndexAllocator`
+#[derive(Clone, Copy, Debug, PartialEq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct CapacityReport {
+    /// Number of slots, occupied or vacant
+    pub slots: usize,
+    pub occupied: usize,
+    /// Number of slots on the free list
+    pub free: usize,
+    pub entries_capacity: usize,
+    pub free_capacity: usize,
+    /// Ratio of free to total slots, 0 without slots
+    pub fragmentation: f64,
+}
+
+// Hooks have to be `Send + Sync` so that the allocator stays `Send` and `Sync` for `Send`/`Sync`
+// values
+type GrowthHook = Box<dyn FnMut(usize, usize) + Send + Sync>;
+type CompactionHook = Box<dyn FnMut(HashMap<GenIndex, GenIndex>) + Send + Sync>;
+type ObserverHook = Box<dyn SlotObserver + Send + Sync>;
+
+/// Kind of a change reported by `GenIndexAllocator::changes_since`
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChangeKind {
+    Added,
+    /// The value was replaced through a method of the allocator
+    Modified,
+    Removed,
+}
+
+/// Position in the change log of a `GenIndexAllocator`
+///
+/// A default cursor starts at the oldest change still in the log.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ChangeCursor {
+    version: u64,
+}
+
+/// Ring buffer of the most recent changes, each tagged with a version
+struct ChangeLog {
+    changes: VecDeque<(u64, GenIndex, ChangeKind)>,
+    capacity: usize,
+    next_version: u64,
+}
+
+impl ChangeLog {
+    fn record(&mut self, key: GenIndex, kind: ChangeKind) {
+        if self.changes.len() == self.capacity {
+            self.changes.pop_front();
+        }
+        if self.capacity > 0 {
+            self.changes.push_back((self.next_version, key, kind));
+        }
+        self.next_version += 1;
+    }
 }
 
 pub struct GenIndexAllocator<T> {
-    entries: Vec<GenIndexEntry<T>>,
+    slots: Vec<Slot>,
+    /// Value of every slot, `None` if the slot is vacant
+    ///
+    /// Always as long as `slots`. Go through `fill` and `take` to change whether a slot is
+    /// occupied, they keep both in sync.
+    values: Vec<Option<T>>,
     free_indices: Vec<usize>,
+    num_occupied: usize,
+    /// Called with the old and new capacity whenever the slots reallocate
+    on_grow: Option<GrowthHook>,
+    overflow_policy: OverflowPolicy,
+    /// Generation of newly pushed slots
+    ///
+    /// When trailing slots are truncated, this is raised to their generation so that stale keys
+    /// for those indices stay invalid once the indices are pushed again.
+    generation_floor: u32,
+    /// Free/total slot ratio above which `deallocate` compacts the allocator
+    auto_compact_threshold: Option<f64>,
+    /// Receives the remap of every automatic compaction
+    on_compact: Option<CompactionHook>,
+    /// Notified with the index of every slot whose value is freed
+    slot_observer: Option<ObserverHook>,
+    /// Largest slot index `allocate` may create
+    max_index: usize,
+    /// Number of additional free list entries to reserve when the free list is full, 0 for the
+    /// default growth of `Vec`
+    free_growth_chunk: usize,
+    change_log: Option<ChangeLog>,
+    /// `(index, old_generation, new_generation)` of every generation bump
+    #[cfg(feature = "reuse-log")]
+    reuse_log: Vec<(usize, u32, u32)>,
 }
 
 impl<T> GenIndexAllocator<T> {
@@ -24,224 +251,4526 @@ impl<T> GenIndexAllocator<T> {
     }
 
     pub fn with_capacity(capacity: usize) -> Self {
+        // We assume that in most use cases, not all indices will be freed at the same time
+        Self::with_capacities(capacity, capacity / 4)
+    }
+
+    /// Create an allocator with separate capacities for the entries and the free list
+    ///
+    /// See `recommended_capacities` for picking the values.
+    pub fn with_capacities(entries_capacity: usize, free_capacity: usize) -> Self {
+        Self {
+            slots: Vec::with_capacity(entries_capacity),
+            values: Vec::with_capacity(entries_capacity),
+            free_indices: Vec::with_capacity(free_capacity),
+            num_occupied: 0,
+            on_grow: None,
+            overflow_policy: OverflowPolicy::default(),
+            generation_floor: 0,
+            auto_compact_threshold: None,
+            on_compact: None,
+            slot_observer: None,
+            max_index: usize::MAX,
+            free_growth_chunk: 0,
+            change_log: None,
+            #[cfg(feature = "reuse-log")]
+            reuse_log: Vec::new(),
+        }
+    }
+
+    /// Create an allocator which never creates a slot index above `max_index`
+    ///
+    /// Going beyond fails with an `IndexTooLarge` error, e.g. to keep indices packable into 32
+    /// bits. Every method that creates slots checks the cap.
+    pub fn with_index_hint(max_index: usize) -> Self {
         Self {
-            entries: Vec::with_capacity(capacity),
-            // We assume that in most use cases, not all indices will be freed at the same time
-            free_indices: Vec::with_capacity(capacity / 4),
+            max_index,
+            ..Self::new()
         }
     }
 
+    /// Create an allocator whose free list has room for `free_capacity` indices
+    ///
+    /// Up to `free_capacity` live entries can be deallocated without reallocating the free list.
+    pub fn with_initial_free_capacity(free_capacity: usize) -> Self {
+        Self {
+            free_indices: Vec::with_capacity(free_capacity),
+            ..Self::new()
+        }
+    }
+
+    /// Recommended `(entries_capacity, free_capacity)` for `with_capacities`
+    ///
+    /// `peak_live` is the expected maximum number of live entries and `expected_churn` the number
+    /// of entries expected to be freed before their slots are reused. Entries get room for the
+    /// peak, the free list for the churn. The free list never holds more indices than there are
+    /// slots, so its capacity is capped at `peak_live`.
+    pub fn recommended_capacities(peak_live: usize, expected_churn: usize) -> (usize, usize) {
+        (peak_live, expected_churn.min(peak_live))
+    }
+
+    /// Create an allocator with `num_free` vacant slots already on the free list
+    ///
+    /// The first `num_free` allocations all reuse a pre-created slot, which keeps their latency
+    /// uniform. Slots are handed out starting from index 0.
+    pub fn new_with_first_free(num_free: usize) -> Self {
+        let mut allocator = Self::with_capacity(num_free.max(100));
+        for _ in 0..num_free {
+            allocator
+                .push_slot(None)
+                .expect("A new allocator has no index cap");
+        }
+        // The free list is used as a stack, so push in reverse to hand out low indices first
+        allocator.free_indices.extend((0..num_free).rev());
+        allocator
+    }
+
+    /// Build an allocator from positional values, with generation 0 for every slot
+    ///
+    /// `None` values become vacant slots on the free list.
+    pub fn from_options(values: Vec<Option<T>>) -> Self {
+        let free_indices = values
+            .iter()
+            .enumerate()
+            .rev()
+            .filter(|(_, value)| value.is_none())
+            .map(|(index, _)| index)
+            .collect();
+        Self::from_options_unchecked(values, free_indices)
+    }
+
+    /// Build an allocator from positional values and a caller-supplied free list
+    ///
+    /// The free list has to contain every `None` position exactly once and nothing else. It is
+    /// used as a stack, so the last index is handed out first.
+    pub fn try_from_options(
+        values: Vec<Option<T>>,
+        free_indices: Vec<usize>,
+    ) -> Result<Self, Error> {
+        let mut listed = vec![false; values.len()];
+        for &index in free_indices.iter() {
+            match values.get(index) {
+                None => bail!("GenIndexAllocator::try_from_options: Free index out of range"),
+                Some(Some(_)) => {
+                    bail!("GenIndexAllocator::try_from_options: Free index points to a value")
+                }
+                Some(None) => {
+                    if listed[index] {
+                        bail!("GenIndexAllocator::try_from_options: Duplicate free index");
+                    }
+                    listed[index] = true;
+                }
+            }
+        }
+        if values
+            .iter()
+            .zip(listed.iter())
+            .any(|(value, &listed)| value.is_none() && !listed)
+        {
+            bail!("GenIndexAllocator::try_from_options: Vacant slot missing from free list");
+        }
+
+        Ok(Self::from_options_unchecked(values, free_indices))
+    }
+
+    fn from_options_unchecked(values: Vec<Option<T>>, free_indices: Vec<usize>) -> Self {
+        let mut allocator = Self::with_capacity(values.len());
+        allocator.num_occupied = values.iter().filter(|value| value.is_some()).count();
+        allocator.slots.extend(values.iter().map(|value| Slot {
+            generation: 0,
+            occupied: value.is_some(),
+        }));
+        allocator.values = values;
+        allocator.free_indices = free_indices;
+        allocator
+    }
+
+    /// Rebuild an allocator from the output of `to_packed`
+    ///
+    /// Every value is placed at the index and generation of its key, so the exported keys stay
+    /// valid. Indices without a key become vacant slots. Generations of vacant slots are not part
+    /// of the packed format, so they restart at 0. Stale keys of the exported allocator may
+    /// therefore become valid again once such a vacant slot is reused.
+    pub fn from_packed(keys: &[GenIndex], values: Vec<T>) -> Result<Self, Error> {
+        if keys.len() != values.len() {
+            bail!("GenIndexAllocator::from_packed: Number of keys and values does not match");
+        }
+
+        let mut num_slots = 0;
+        for key in keys.iter() {
+            match key.index.checked_add(1) {
+                Some(end) => num_slots = num_slots.max(end),
+                None => bail!("GenIndexAllocator::from_packed: Index of key is too large"),
+            }
+        }
+        let mut allocator = Self::new();
+        if allocator.slots.try_reserve_exact(num_slots).is_err()
+            || allocator.values.try_reserve_exact(num_slots).is_err()
+        {
+            bail!(
+                "GenIndexAllocator::from_packed: Could not reserve slots up to the largest index"
+            );
+        }
+        allocator.slots.resize(
+            num_slots,
+            Slot {
+                generation: 0,
+                occupied: false,
+            },
+        );
+        allocator.values.resize_with(num_slots, || None);
+
+        for (key, value) in keys.iter().zip(values) {
+            if allocator.slots[key.index].occupied {
+                bail!("GenIndexAllocator::from_packed: Duplicate index in keys");
+            }
+            allocator.slots[key.index].generation = key.generation;
+            allocator.fill(key.index, value);
+        }
+
+        allocator.num_occupied = keys.len();
+        let free_indices = (0..num_slots)
+            .rev()
+            .filter(|&index| !allocator.slots[index].occupied);
+        allocator.free_indices = free_indices.collect();
+        Ok(allocator)
+    }
+
+    /// Allocate every item of `iter` and return the allocator with the keys in iteration order
+    pub fn collect_with_keys<I: IntoIterator<Item = T>>(iter: I) -> (Self, Vec<GenIndex>) {
+        let mut allocator = Self::new();
+        let keys = iter
+            .into_iter()
+            .map(|value| {
+                allocator
+                    .allocate(value)
+                    .expect("Allocating in a fresh allocator should not fail")
+            })
+            .collect();
+        (allocator, keys)
+    }
+
     pub fn allocate(&mut self, value: T) -> Result<GenIndex, Error> {
         match self.free_indices.pop() {
             None => {
-                let new_key = GenIndex {
-                    index: self.entries.len(),
-                    generation: 0,
-                };
-                self.entries.push(GenIndexEntry {
-                    key: new_key,
-                    value: Some(value),
-                });
+                let new_key = self.push_slot(Some(value))?;
+                self.num_occupied += 1;
+                self.record_change(new_key, ChangeKind::Added);
                 Ok(new_key)
             }
-            Some(free_idx) => match self.entries.get_mut(free_idx) {
+            Some(free_idx) => match self.slots.get(free_idx) {
                 None => bail!(
                     "GenIndexAllocator::allocate: Could not find free index that should exist"
                 ),
-                Some(entry) => {
-                    entry.key.generation += 1;
-                    entry.value.replace(value);
-                    Ok(entry.key)
+                Some(_) => {
+                    self.fill(free_idx, value);
+                    let new_key = self.key_at(free_idx);
+                    self.num_occupied += 1;
+                    self.record_change(new_key, ChangeKind::Added);
+                    Ok(new_key)
                 }
             },
         }
     }
 
-    pub fn deallocate(&mut self, key: &GenIndex) -> Result<Option<T>, Error> {
-        match self.entries.get_mut(key.index) {
-            None => bail!("GenIndexAllocator::deallocate: Index not found"),
-            Some(entry) => {
-                if entry.key.generation != key.generation {
-                    bail!("GenIndexAllocator::deallocate: Wrong generation");
-                }
+    /// Append a new slot and report growth of the slots to the growth hook
+    ///
+    /// Every method adding slots goes through here, so that the hook sees all growth and the
+    /// index cap is always enforced.
+    fn push_slot(&mut self, value: Option<T>) -> Result<GenIndex, Error> {
+        self.check_index(self.slots.len())?;
+        let new_key = GenIndex {
+            index: self.slots.len(),
+            generation: self.generation_floor,
+        };
+        let old_capacity = self.slots.capacity();
+        self.slots.push(Slot {
+            generation: new_key.generation,
+            occupied: value.is_some(),
+        });
+        self.values.push(value);
+        self.report_capacity_change(old_capacity);
+        Ok(new_key)
+    }
 
-                let value = entry.value.take();
-                self.free_indices.push(key.index);
-                Ok(value)
-            }
+    /// Key of the slot at `index` with its current generation, whether occupied or not
+    fn key_at(&self, index: usize) -> GenIndex {
+        GenIndex {
+            index,
+            generation: self.slots[index].generation,
         }
     }
 
-    pub fn get(&self, key: &GenIndex) -> Option<&T> {
-        match self.entries.get(key.index) {
-            None => None,
-            Some(entry) => {
-                if entry.key.generation != key.generation {
-                    return None;
-                }
+    /// Store `value` in the slot at `index` and mark it occupied, returning the previous value
+    ///
+    /// Neither the occupied count nor the free list are updated.
+    fn fill(&mut self, index: usize, value: T) -> Option<T> {
+        self.slots[index].occupied = true;
+        self.values[index].replace(value)
+    }
 
-                (entry.value).as_ref()
-            }
+    /// Take the value out of the slot at `index` and mark it vacant
+    ///
+    /// Neither the occupied count nor the free list are updated.
+    fn take(&mut self, index: usize) -> Option<T> {
+        self.slots[index].occupied = false;
+        self.values[index].take()
+    }
+
+    fn value_at_mut(&mut self, index: usize) -> Option<&mut T> {
+        if !self.slots.get(index)?.occupied {
+            return None;
         }
+        self.values[index].as_mut()
     }
 
-    pub fn get_mut(&mut self, key: &GenIndex) -> Option<&mut T> {
-        match self.entries.get_mut(key.index) {
-            None => None,
-            Some(entry) => {
-                if entry.key.generation != key.generation {
-                    return None;
-                }
+    /// Occupied slots with their keys and mutable values, in index order
+    fn live_mut(&mut self) -> impl Iterator<Item = (GenIndex, &mut T)> {
+        self.slots
+            .iter()
+            .zip(self.values.iter_mut())
+            .enumerate()
+            .filter(|(_, (slot, _))| slot.occupied)
+            .filter_map(|(index, (slot, value))| {
+                let key = GenIndex {
+                    index,
+                    generation: slot.generation,
+                };
+                Some((key, value.as_mut()?))
+            })
+    }
 
-                (entry.value).as_mut()
-            }
+    /// Fail with `IndexTooLarge` if `index` is above the cap set with `with_index_hint`
+    fn check_index(&self, index: usize) -> Result<(), Error> {
+        if index > self.max_index {
+            return Err(Box::new(IndexTooLarge {
+                index,
+                max_index: self.max_index,
+            }));
         }
+        Ok(())
     }
 
-    pub fn set(&mut self, key: &GenIndex, value: T) -> Result<T, Error> {
-        match self.entries.get_mut(key.index) {
-            None => bail!("GenIndexAllocator::set: Entry for key not found"),
-            Some(entry) => {
-                if entry.key.generation != key.generation {
-                    bail!("GenIndexAllocator::set: Entry exists but generation does not match");
-                }
-
-                entry
-                    .value
-                    .replace(value)
-                    .ok_or_else(|| {
-                        simple_error::SimpleError::new(
-                            "GenIndexAllocator::set: Entry to overwrite is empty but should not be",
-                        )
-                    })
-                    .map_err(|e| e.into())
+    /// Call the growth hook if the capacity of the slots differs from `old_capacity`
+    fn report_capacity_change(&mut self, old_capacity: usize) {
+        if let Some(on_grow) = self.on_grow.as_mut() {
+            let new_capacity = self.slots.capacity();
+            if new_capacity != old_capacity {
+                on_grow(old_capacity, new_capacity);
             }
         }
     }
-}
 
-impl<T> Default for GenIndexAllocator<T> {
-    fn default() -> Self {
-        Self::new()
+    /// Reserve a slot whose key is known before its value is stored
+    ///
+    /// Call `commit` on the returned guard to store the value. If the guard is dropped without
+    /// committing, the slot is freed again and its key becomes invalid. A slot at the generation
+    /// ceiling is retired then, unless the policy is `Wrap`. Fails like `allocate` if a new slot
+    /// would be above the index cap.
+    pub fn begin_reserve(&mut self) -> Result<ReservedSlot<'_, T>, Error> {
+        let index = match self.free_indices.pop() {
+            Some(free_idx) => free_idx,
+            None => self.push_slot(None)?.index,
+        };
+        Ok(ReservedSlot {
+            allocator: self,
+            index,
+            committed: false,
+        })
     }
-}
-
-#[cfg(test)]
-mod test {
-    use super::*;
 
-    #[test]
-    fn test_create_with_capacity() -> Result<(), Error> {
-        let capacity = 200;
-        let gen_alloc = GenIndexAllocator::<i32>::with_capacity(capacity);
-        assert_eq!(gen_alloc.entries.capacity(), capacity);
-        Ok(())
+    /// Try to reserve room for exactly `additional` more slots, see `Vec::try_reserve_exact`
+    pub fn try_reserve_exact(&mut self, additional: usize) -> Result<(), TryReserveError> {
+        let old_capacity = self.slots.capacity();
+        self.slots.try_reserve_exact(additional)?;
+        // The slots may have grown already, so the hook has to hear of it either way
+        let reserved_values = self.values.try_reserve_exact(additional);
+        self.report_capacity_change(old_capacity);
+        reserved_values
     }
 
-    #[test]
-    fn test_allocate_and_get() -> Result<(), Error> {
-        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+    /// Append `n` reserved slots at the end and return their keys, with consecutive indices
+    ///
+    /// The free list is bypassed on purpose so that the slots are adjacent. Fill them with `put`
+    /// and give back the ones not needed with `release_reserved`, otherwise they stay reserved
+    /// and are never handed out again.
+    ///
+    /// Fails without creating any slot if the last index would be above the index cap.
+    pub fn reserve_contiguous(&mut self, n: usize) -> Result<Vec<GenIndex>, Error> {
+        if n > 0 {
+            self.check_index(self.slots.len().saturating_add(n - 1))?;
+        }
+        (0..n).map(|_| self.push_slot(None)).collect()
+    }
 
-        // Create value and check it
-        let value1 = 1i32;
-        let key1 = gen_alloc.allocate(value1)?;
-        assert_eq!(gen_alloc.entries.len(), 1);
-        assert_eq!(gen_alloc.get(&key1), Some(&value1));
+    /// Put a reserved slot that will not be filled on the free list, invalidating `key`
+    ///
+    /// Fails for stale keys and if the slot is occupied, already free or retired.
+    pub fn release_reserved(&mut self, key: &GenIndex) -> Result<(), Error> {
+        match self.slots.get(key.index) {
+            None => bail!("GenIndexAllocator::release_reserved: Index not found"),
+            Some(slot) => {
+                if slot.generation != key.generation {
+                    bail!("GenIndexAllocator::release_reserved: Wrong generation");
+                }
+                if slot.occupied {
+                    bail!("GenIndexAllocator::release_reserved: Slot is occupied");
+                }
+            }
+        }
+        if self.free_indices.contains(&key.index) {
+            bail!("GenIndexAllocator::release_reserved: Slot is already free");
+        }
+        let Some(generation) = self.next_generation(key.generation) else {
+            bail!("GenIndexAllocator::release_reserved: Slot is retired");
+        };
 
+        self.slots[key.index].generation = generation;
+        #[cfg(feature = "reuse-log")]
+        self.reuse_log.push((key.index, key.generation, generation));
+        self.push_free(key.index);
         Ok(())
     }
 
-    #[test]
-    fn test_allocate_and_set() -> Result<(), Error> {
-        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+    /// Allocate `value` and return only the raw slot index
+    ///
+    /// The generation is still tracked internally, but the returned index on its own gives no
+    /// protection against reuse of the slot. Use `generation_at` right away to build a full key if
+    /// needed, a key built later from a wrong generation can refer to a different value.
+    pub fn allocate_index(&mut self, value: T) -> Result<usize, Error> {
+        self.allocate(value).map(|key| key.index)
+    }
 
-        // Create value and check it
-        let value1 = 1i32;
-        let key1 = gen_alloc.allocate(value1)?;
-        assert_eq!(gen_alloc.entries.len(), 1);
-        assert_eq!(gen_alloc.get(&key1), Some(&value1));
+    /// Register a hook called with `(old_capacity, new_capacity)` whenever the slots reallocate
+    ///
+    /// This covers every method adding slots as well as `try_reserve_exact` and the shrinking in
+    /// `shrink_and_remap`.
+    pub fn set_capacity_growth_hook(
+        &mut self,
+        hook: impl FnMut(usize, usize) + Send + Sync + 'static,
+    ) {
+        self.on_grow = Some(Box::new(hook));
+    }
 
-        // Create value and check it
-        let value2 = 2i32;
-        let key2 = gen_alloc.allocate(value2)?;
-        assert_eq!(gen_alloc.entries.len(), 2);
-        assert_eq!(gen_alloc.get(&key2), Some(&value2));
+    /// Register an observer notified whenever a value is freed, by any method
+    ///
+    /// Replaces a previously registered observer.
+    pub fn set_slot_observer(&mut self, observer: impl SlotObserver + Send + Sync + 'static) {
+        self.slot_observer = Some(Box::new(observer));
+    }
 
-        // Set first key to different value - the second value should be unchanged
-        let new_value1 = 99i32;
-        gen_alloc.set(&key1, new_value1)?;
-        assert_eq!(gen_alloc.entries.len(), 2);
-        assert_eq!(gen_alloc.get(&key1), Some(&new_value1));
-        assert_eq!(gen_alloc.get(&key2), Some(&value2));
+    pub fn remove_slot_observer(&mut self) {
+        self.slot_observer = None;
+    }
 
-        Ok(())
+    fn notify_free(&mut self, index: usize) {
+        if let Some(observer) = self.slot_observer.as_mut() {
+            observer.on_free(index);
+        }
     }
 
-    #[test]
-    fn test_reuse_free_indices() -> Result<(), Error> {
-        let capacity = 5;
-        let mut gen_alloc = GenIndexAllocator::with_capacity(capacity);
-        assert_eq!(gen_alloc.entries.len(), 0);
-        assert_eq!(gen_alloc.entries.capacity(), capacity);
+    pub fn remove_capacity_growth_hook(&mut self) {
+        self.on_grow = None;
+    }
 
-        let mut alloced_keys: Vec<_> = (0..capacity)
-            .into_iter()
-            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
-            .collect();
+    pub fn overflow_policy(&self) -> OverflowPolicy {
+        self.overflow_policy
+    }
 
-        // We created the values in order - check that each key points to the right value
-        for (value, key) in alloced_keys.iter().enumerate() {
-            assert_eq!(gen_alloc.get(key), Some(&value));
-        }
+    pub fn set_overflow_policy(&mut self, policy: OverflowPolicy) {
+        self.overflow_policy = policy;
+    }
 
-        // Split the keys and free some of them
-        let num_keys_to_free = 2;
-        let to_free = alloced_keys.split_off(capacity - num_keys_to_free);
+    pub fn deallocate(&mut self, key: &GenIndex) -> Result<Option<T>, Error> {
+        match self.slots.get(key.index) {
+            None => bail!("GenIndexAllocator::deallocate: Index not found"),
+            Some(slot) => {
+                if slot.generation != key.generation {
+                    bail!("GenIndexAllocator::deallocate: Wrong generation");
+                }
+                if self.overflow_policy == OverflowPolicy::Error
+                    && slot.occupied
+                    && slot.generation == u32::MAX
+                {
+                    bail!("GenIndexAllocator::deallocate: Generation overflow");
+                }
 
-        for key in to_free.iter() {
-            gen_alloc.deallocate(key)?;
+                let value = self.release(key.index);
+                self.auto_compact();
+                Ok(value)
+            }
         }
+    }
 
-        assert_eq!(
-            gen_alloc.entries.len(),
-            capacity,
-            "We do not remove entries so the length should be unchanged"
-        );
-        assert_eq!(
-            gen_alloc.entries.capacity(),
-            capacity,
-            "We do not exceed capacity so it should be unchanged"
-        );
+    /// Deallocate the entry of `key` only if `pred` returns `true` for its value
+    ///
+    /// Returns `Ok(None)` without removing anything if `pred` returns `false` or the slot is
+    /// vacant. Stale and out-of-range keys are errors like in `deallocate`.
+    pub fn deallocate_if<F: FnOnce(&T) -> bool>(
+        &mut self,
+        key: &GenIndex,
+        pred: F,
+    ) -> Result<Option<T>, Error> {
+        match self.slots.get(key.index) {
+            None => bail!("GenIndexAllocator::deallocate_if: Index not found"),
+            Some(slot) => {
+                if slot.generation != key.generation {
+                    bail!("GenIndexAllocator::deallocate_if: Wrong generation");
+                }
+                match self.value_at(key.index) {
+                    Some(value) if pred(value) => self.deallocate(key),
+                    _ => Ok(None),
+                }
+            }
+        }
+    }
 
-        // Reuse indices, the capacity should be unchanged but old keys should get invalid
-        let reused_entries_keys: Vec<_> = (0..num_keys_to_free)
-            .into_iter()
-            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
-            .collect();
+    /// Compact automatically in `deallocate` once the ratio of free to total slots exceeds
+    /// `threshold`, passing the remap of every compaction to `on_compact`
+    ///
+    /// Be aware that this makes `deallocate` move other entries and invalidate their keys.
+    /// Everything holding keys has to be updated from the remap in `on_compact`.
+    pub fn enable_auto_compact(
+        &mut self,
+        threshold: f64,
+        on_compact: impl FnMut(HashMap<GenIndex, GenIndex>) + Send + Sync + 'static,
+    ) {
+        self.auto_compact_threshold = Some(threshold);
+        self.on_compact = Some(Box::new(on_compact));
+    }
 
-        assert_eq!(
-            gen_alloc.entries.len(),
-            capacity,
-            "We do not remove entries so the length should be unchanged"
-        );
-        assert_eq!(
-            gen_alloc.entries.capacity(),
-            capacity,
-            "We do not exceed capacity so it should be unchanged"
-        );
+    pub fn disable_auto_compact(&mut self) {
+        self.auto_compact_threshold = None;
+        self.on_compact = None;
+    }
 
-        for key in to_free.iter() {
-            assert_eq!(
-                gen_alloc.get(key),
-                None,
-                "This key should be invalid because the generation does not match"
-            );
+    fn auto_compact(&mut self) {
+        let Some(threshold) = self.auto_compact_threshold else {
+            return;
+        };
+        if self.slots.is_empty()
+            || (self.free_indices.len() as f64 / self.slots.len() as f64) <= threshold
+        {
+            return;
         }
 
-        for (value, key) in reused_entries_keys.iter().enumerate() {
-            assert_eq!(gen_alloc.get(key), Some(&value));
+        let remap = self.compact();
+        if let Some(on_compact) = self.on_compact.as_mut() {
+            on_compact(remap);
         }
+    }
 
-        Ok(())
+    /// Move live entries into the lowest free slots and drop trailing free slots
+    ///
+    /// Returns a map from old to new key for every live entry, including entries that stay in
+    /// place. Old keys of moved entries are invalid afterwards. Vacant slots which are not on the
+    /// free list (e.g. retired slots) are left alone.
+    pub fn compact(&mut self) -> HashMap<GenIndex, GenIndex> {
+        let mut remap: HashMap<_, _> = self.keys().map(|key| (key, key)).collect();
+
+        let (moves, unused_targets) = self.compaction_moves();
+        self.free_indices.clear();
+        for (source, target) in moves {
+            let old_key = self.key_at(source);
+            // Releasing the source puts it on the (new) free list
+            let value = self
+                .release(source)
+                .expect("Source slot should be occupied");
+            self.fill(target, value);
+            self.num_occupied += 1;
+            let new_key = self.key_at(target);
+            self.record_change(new_key, ChangeKind::Added);
+            remap.insert(old_key, new_key);
+        }
+
+        self.free_indices.extend(unused_targets);
+        self.truncate_free_tail();
+        // Hand out low indices first
+        self.free_indices.sort_unstable_by(|a, b| b.cmp(a));
+        remap
+    }
+
+    /// Remap `compact` would return right now, without changing anything
+    pub fn compaction_plan(&self) -> HashMap<GenIndex, GenIndex> {
+        let mut remap: HashMap<_, _> = self.keys().map(|key| (key, key)).collect();
+        let (moves, _) = self.compaction_moves();
+        for (source, target) in moves {
+            remap.insert(self.key_at(source), self.key_at(target));
+        }
+        remap
+    }
+
+    /// `(source, target)` slot indices of the moves `compact` makes, and the free indices that
+    /// receive no value
+    ///
+    /// Targets are taken from the free list in ascending order, each filled with the highest live
+    /// entry above it. Free list entries pointing to occupied slots are dropped.
+    fn compaction_moves(&self) -> (Vec<(usize, usize)>, Vec<usize>) {
+        let mut targets = self.free_indices.clone();
+        targets.sort_unstable();
+        targets.dedup();
+
+        let mut moves = Vec::new();
+        let mut unused_targets = Vec::new();
+        let mut source_end = self.slots.len();
+        for target in targets {
+            if self.value_at(target).is_some() {
+                // Corrupted free list entry, drop it
+                continue;
+            }
+            let source = (target + 1..source_end)
+                .rev()
+                .find(|&index| self.slots[index].occupied);
+            match source {
+                None => unused_targets.push(target),
+                Some(source) => {
+                    source_end = source;
+                    moves.push((source, target));
+                }
+            }
+        }
+        (moves, unused_targets)
+    }
+
+    /// Move live entries to the front in index order, drop all vacant slots and reset every
+    /// generation to 0
+    ///
+    /// **Danger:** Since generations start over, keys handed out before can become valid again
+    /// for unrelated values. Only use this when no keys except those in the returned remap are
+    /// kept anywhere, e.g. right before serializing. Reserved slots are dropped as well.
+    pub fn compact_and_renumber(&mut self) -> HashMap<GenIndex, GenIndex> {
+        let mut remap = HashMap::with_capacity(self.num_occupied);
+        let capacity = self.slots.capacity();
+        let old_slots = std::mem::replace(&mut self.slots, Vec::with_capacity(capacity));
+        let old_values = std::mem::replace(&mut self.values, Vec::with_capacity(capacity));
+        for (index, (slot, value)) in old_slots.into_iter().zip(old_values).enumerate() {
+            let old_key = GenIndex {
+                index,
+                generation: slot.generation,
+            };
+            if let Some(value) = value.filter(|_| slot.occupied) {
+                let new_key = GenIndex {
+                    index: self.slots.len(),
+                    generation: 0,
+                };
+                self.slots.push(Slot {
+                    generation: 0,
+                    occupied: true,
+                });
+                self.values.push(Some(value));
+                if new_key != old_key {
+                    self.notify_free(old_key.index);
+                    self.record_change(old_key, ChangeKind::Removed);
+                    self.record_change(new_key, ChangeKind::Added);
+                }
+                remap.insert(old_key, new_key);
+            }
+        }
+        self.free_indices.clear();
+        self.generation_floor = 0;
+        remap
+    }
+
+    /// Compact and release all spare capacity, returning the remap of `compact`
+    pub fn shrink_and_remap(&mut self) -> HashMap<GenIndex, GenIndex> {
+        let remap = self.compact();
+        let old_capacity = self.slots.capacity();
+        self.slots.shrink_to_fit();
+        self.values.shrink_to_fit();
+        self.report_capacity_change(old_capacity);
+        self.free_indices.shrink_to_fit();
+        remap
+    }
+
+    /// Drop trailing slots which are on the free list
+    fn truncate_free_tail(&mut self) {
+        let mut is_free = vec![false; self.slots.len()];
+        for &index in self.free_indices.iter() {
+            is_free[index] = true;
+        }
+
+        while let Some(slot) = self.slots.last() {
+            if slot.occupied || !is_free[self.slots.len() - 1] {
+                break;
+            }
+            self.generation_floor = self.generation_floor.max(slot.generation);
+            self.slots.pop();
+            self.values.pop();
+        }
+
+        let num_slots = self.slots.len();
+        self.free_indices.retain(|&index| index < num_slots);
+    }
+
+    /// Deallocate `key` and push its value into `pool` instead of dropping it
+    pub fn recycle_into(&mut self, key: &GenIndex, pool: &mut Vec<T>) -> Result<(), Error> {
+        if let Some(value) = self.deallocate(key)? {
+            pool.push(value);
+        }
+        Ok(())
+    }
+
+    /// Deallocate all entries for which `keep` returns `false` and pass them to `on_remove`
+    pub fn retain_with<F, G>(&mut self, mut keep: F, mut on_remove: G)
+    where
+        F: FnMut(GenIndex, &T) -> bool,
+        G: FnMut(GenIndex, T),
+    {
+        for index in 0..self.slots.len() {
+            let key = self.key_at(index);
+            if let Some(value) = self.value_at(index) {
+                if !keep(key, value) {
+                    let value = self.release(index).expect("Slot should be occupied");
+                    on_remove(key, value);
+                }
+            }
+        }
+    }
+
+    /// Take the value out of the slot at `index` and put the slot on the free list
+    ///
+    /// The generation is bumped right away, so vacant slots always carry the generation their
+    /// next value will get and keys of the released value are invalid immediately.
+    ///
+    /// If the generation cannot be advanced, the slot is retired instead: it stays vacant and is
+    /// never handed out again.
+    fn release(&mut self, index: usize) -> Option<T> {
+        let old_key = self.key_at(index);
+        let next_generation = self.next_generation(old_key.generation);
+        let value = self.take(index);
+        // Releasing an already vacant slot must not add its index to the free list twice
+        if value.is_some() {
+            self.num_occupied -= 1;
+            if let Some(generation) = next_generation {
+                self.slots[index].generation = generation;
+                #[cfg(feature = "reuse-log")]
+                self.reuse_log.push((index, old_key.generation, generation));
+                self.push_free(index);
+            }
+            self.notify_free(index);
+            self.record_change(old_key, ChangeKind::Removed);
+        }
+        value
+    }
+
+    fn push_free(&mut self, index: usize) {
+        if self.free_indices.len() == self.free_indices.capacity() && self.free_growth_chunk > 0 {
+            self.free_indices.reserve_exact(self.free_growth_chunk);
+        }
+        self.free_indices.push(index);
+    }
+
+    /// Reserve room for at least `additional` more free list entries
+    pub fn reserve_free(&mut self, additional: usize) {
+        self.free_indices.reserve(additional);
+    }
+
+    /// Grow the free list by exactly `chunk` entries whenever it is full, 0 for the default
+    /// growth of `Vec`
+    pub fn set_free_growth_chunk(&mut self, chunk: usize) {
+        self.free_growth_chunk = chunk;
+    }
+
+    /// Generation following `generation` according to the overflow policy
+    ///
+    /// Returns `None` if the slot has to be retired.
+    fn next_generation(&self, generation: u32) -> Option<u32> {
+        match self.overflow_policy {
+            OverflowPolicy::Panic => Some(
+                generation
+                    .checked_add(1)
+                    .expect("GenIndexAllocator: Generation overflow"),
+            ),
+            OverflowPolicy::Wrap => Some(generation.wrapping_add(1)),
+            OverflowPolicy::Error | OverflowPolicy::Retire => generation.checked_add(1),
+        }
+    }
+
+    fn record_change(&mut self, key: GenIndex, kind: ChangeKind) {
+        if let Some(change_log) = self.change_log.as_mut() {
+            change_log.record(key, kind);
+        }
+    }
+
+    /// `(index, old_generation, new_generation)` of every generation bump in order, to trace
+    /// slot reuse
+    #[cfg(feature = "reuse-log")]
+    pub fn reuse_log(&self) -> &[(usize, u32, u32)] {
+        &self.reuse_log
+    }
+
+    #[cfg(feature = "reuse-log")]
+    pub fn clear_reuse_log(&mut self) {
+        self.reuse_log.clear();
+    }
+
+    /// Record the last `capacity` changes for `changes_since`
+    ///
+    /// Allocations, removals and value replacements through `set`, `put` and `map_in_place` are
+    /// recorded. Changes through mutable references, e.g. from `get_mut`, are not. If the log is
+    /// already enabled, only its capacity changes.
+    pub fn enable_change_log(&mut self, capacity: usize) {
+        let change_log = self.change_log.get_or_insert_with(|| ChangeLog {
+            changes: VecDeque::with_capacity(capacity),
+            capacity,
+            next_version: 0,
+        });
+        change_log.capacity = capacity;
+        let excess = change_log.changes.len().saturating_sub(capacity);
+        change_log.changes.drain(..excess);
+    }
+
+    pub fn disable_change_log(&mut self) {
+        self.change_log = None;
+    }
+
+    /// Changes recorded since `cursor` in the order they happened, advancing `cursor` to the
+    /// latest change
+    ///
+    /// Changes that were already dropped from the log are skipped. Nothing is reported if the log
+    /// is disabled.
+    pub fn changes_since(
+        &self,
+        cursor: &mut ChangeCursor,
+    ) -> impl Iterator<Item = (GenIndex, ChangeKind)> + '_ {
+        let since = cursor.version;
+        if let Some(change_log) = self.change_log.as_ref() {
+            cursor.version = change_log.next_version;
+        }
+        self.change_log
+            .iter()
+            .flat_map(|change_log| change_log.changes.iter())
+            .filter(move |(version, _, _)| *version >= since)
+            .map(|&(_, key, kind)| (key, kind))
+    }
+
+    /// Make sure a slot exists at `index` and return its current key
+    ///
+    /// Missing slots up to `index` are created as vacant slots and put on the free list. For a
+    /// vacant slot, the returned key is the key the next value stored in the slot will get.
+    ///
+    /// Fails without creating any slot if `index` is above the index cap, and fails for a retired
+    /// slot since no value will ever be stored there again.
+    pub fn ensure_index(&mut self, index: usize) -> Result<GenIndex, Error> {
+        if self.is_retired(index) {
+            bail!("GenIndexAllocator::ensure_index: Slot is retired");
+        }
+        let num_slots = self.slots.len();
+        if index >= num_slots {
+            self.check_index(index)?;
+            let old_capacity = self.slots.capacity();
+            self.slots.reserve(index + 1 - num_slots);
+            self.values.reserve(index + 1 - num_slots);
+            self.report_capacity_change(old_capacity);
+            for _ in num_slots..=index {
+                self.push_slot(None)?;
+            }
+            self.free_indices.extend((num_slots..=index).rev());
+        }
+        Ok(self.key_at(index))
+    }
+
+    /// Key of the value at `index`, storing `f()` there first if the slot is vacant or missing
+    ///
+    /// Missing slots up to `index` are created like in `ensure_index`. The new value gets the
+    /// generation the vacant slot carries, so stale keys for the slot stay invalid.
+    ///
+    /// Fails if the slot at `index` is retired or `index` is above the index cap.
+    pub fn get_or_spawn(&mut self, index: usize, f: impl FnOnce() -> T) -> Result<GenIndex, Error> {
+        if let Some(key) = self.current_key_at(index) {
+            return Ok(key);
+        }
+        if self.is_retired(index) {
+            bail!("GenIndexAllocator::get_or_spawn: Slot is retired");
+        }
+
+        let key = self.ensure_index(index)?;
+        self.claim_vacant(index, "get_or_spawn")?;
+        self.fill(index, f());
+        self.num_occupied += 1;
+        self.record_change(key, ChangeKind::Added);
+        Ok(key)
+    }
+
+    pub fn get(&self, key: &GenIndex) -> Option<&T> {
+        match self.slots.get(key.index) {
+            None => None,
+            Some(slot) => {
+                if slot.generation != key.generation || !slot.occupied {
+                    return None;
+                }
+
+                self.values[key.index].as_ref()
+            }
+        }
+    }
+
+    /// Like `get`, but also return the current key of the slot
+    ///
+    /// The key equals `key` for a valid lookup, the shape matches `iter` and `current_key_at`.
+    pub fn get_entry(&self, key: &GenIndex) -> Option<(GenIndex, &T)> {
+        self.get(key).map(|value| (*key, value))
+    }
+
+    /// Resolve two keys at once, the keys may be the same
+    pub fn get2(&self, a: &GenIndex, b: &GenIndex) -> (Option<&T>, Option<&T>) {
+        (self.get(a), self.get(b))
+    }
+
+    /// Mutable access to the two distinct entries of `a` and `b` at once, e.g. to resolve an
+    /// interaction between them
+    ///
+    /// Validity is checked first, so `SameEntity` is only reported for two valid keys.
+    pub fn get_mut_pair(
+        &mut self,
+        a: &GenIndex,
+        b: &GenIndex,
+    ) -> Result<(&mut T, &mut T), PairError> {
+        if !self.contains(a) {
+            return Err(PairError::FirstInvalid);
+        }
+        if !self.contains(b) {
+            return Err(PairError::SecondInvalid);
+        }
+
+        match self.get_pair_mut_by_index(a.index, b.index) {
+            Some((Some(first), Some(second))) => Ok((first, second)),
+            _ => Err(PairError::SameEntity),
+        }
+    }
+
+    /// Mutable access to both entries of every pair at once
+    ///
+    /// All slot indices across the whole batch have to be distinct, otherwise the first repeated
+    /// index is reported. The cost grows with the size of the batch, not with the number of slots.
+    pub fn get_disjoint_pairs_mut(
+        &mut self,
+        pairs: &[(GenIndex, GenIndex)],
+    ) -> Result<Vec<(&mut T, &mut T)>, GetManyError> {
+        if let Some(invalid_key) = pairs
+            .iter()
+            .flat_map(|(a, b)| [a, b])
+            .find(|key| !self.contains(key))
+        {
+            return Err(GetManyError::InvalidKey(*invalid_key));
+        }
+
+        // Positions of the keys in the batch, sorted by slot index, to find repeats and to visit
+        // the slots in a single forward pass
+        let keys: Vec<&GenIndex> = pairs.iter().flat_map(|(a, b)| [a, b]).collect();
+        let mut order: Vec<usize> = (0..keys.len()).collect();
+        order.sort_unstable_by_key(|&position| (keys[position].index, position));
+        if let Some(repeated) = order
+            .windows(2)
+            .filter(|window| keys[window[0]].index == keys[window[1]].index)
+            .map(|window| window[1])
+            .min()
+        {
+            return Err(GetManyError::DuplicateIndex(keys[repeated].index));
+        }
+
+        let mut values: Vec<Option<&mut T>> = (0..keys.len()).map(|_| None).collect();
+        let mut cells = self.values.iter_mut();
+        let mut next_index = 0;
+        for position in order {
+            let index = keys[position].index;
+            let cell = cells
+                .nth(index - next_index)
+                .expect("Keys were checked to be valid");
+            values[position] = cell.as_mut();
+            next_index = index + 1;
+        }
+
+        let mut values = values
+            .into_iter()
+            .map(|value| value.expect("Keys were checked to be valid"));
+        Ok(pairs
+            .iter()
+            .map(|_| (values.next().unwrap(), values.next().unwrap()))
+            .collect())
+    }
+
+    /// Value stored in the slot at `index`, regardless of its generation
+    ///
+    /// Returns `None` for vacant slots and out-of-range indices.
+    pub fn value_at(&self, index: usize) -> Option<&T> {
+        if !self.slots.get(index)?.occupied {
+            return None;
+        }
+        self.values[index].as_ref()
+    }
+
+    /// Mutable access to the value cells of the slots at `a` and `b`, regardless of generations
+    ///
+    /// Returns `None` if an index is out of range or both indices are equal.
+    ///
+    /// **Danger:** The free list and the occupied count are not updated. Swapping two occupied or
+    /// two vacant cells, or changing values in place, is fine. Emptying or filling a cell leaves
+    /// the allocator inconsistent.
+    pub fn get_pair_mut_by_index(
+        &mut self,
+        a: usize,
+        b: usize,
+    ) -> Option<(&mut Option<T>, &mut Option<T>)> {
+        if a == b || a.max(b) >= self.values.len() {
+            return None;
+        }
+
+        let (low, high) = self.values.split_at_mut(a.max(b));
+        let (low, high) = (&mut low[a.min(b)], &mut high[0]);
+        if a < b {
+            Some((low, high))
+        } else {
+            Some((high, low))
+        }
+    }
+
+    pub fn get_mut(&mut self, key: &GenIndex) -> Option<&mut T> {
+        match self.slots.get(key.index) {
+            None => None,
+            Some(slot) => {
+                if slot.generation != key.generation {
+                    return None;
+                }
+
+                self.value_at_mut(key.index)
+            }
+        }
+    }
+
+    pub fn set(&mut self, key: &GenIndex, value: T) -> Result<T, Error> {
+        match self.slots.get(key.index) {
+            None => bail!("GenIndexAllocator::set: Entry for key not found"),
+            Some(slot) => {
+                if slot.generation != key.generation {
+                    bail!("GenIndexAllocator::set: Entry exists but generation does not match");
+                }
+
+                match self.value_at_mut(key.index) {
+                    None => bail!(
+                        "GenIndexAllocator::set: Entry to overwrite is empty but should not be"
+                    ),
+                    Some(old_value) => {
+                        let old_value = std::mem::replace(old_value, value);
+                        self.record_change(*key, ChangeKind::Modified);
+                        Ok(old_value)
+                    }
+                }
+            }
+        }
+    }
+
+    /// Store `value` for `key` and return the previous value, like `HashMap::insert`
+    ///
+    /// Unlike `set`, this also fills a vacant slot if `key` has the generation the next value in
+    /// that slot will get, e.g. a key returned by `ensure_index`. Only stale and out-of-range keys
+    /// are rejected.
+    pub fn put(&mut self, key: &GenIndex, value: T) -> Result<Option<T>, Error> {
+        match self.slots.get(key.index) {
+            None => bail!("GenIndexAllocator::put: Index not found"),
+            Some(slot) => {
+                if slot.generation != key.generation {
+                    bail!("GenIndexAllocator::put: Wrong generation");
+                }
+                let kind = if !slot.occupied {
+                    self.claim_vacant(key.index, "put")?;
+                    self.num_occupied += 1;
+                    ChangeKind::Added
+                } else {
+                    ChangeKind::Modified
+                };
+
+                self.record_change(*key, kind);
+                Ok(self.fill(key.index, value))
+            }
+        }
+    }
+
+    /// Store every value at the exact index and generation of its key
+    ///
+    /// Missing slots are created as vacant slots. Fails without changing anything if a key
+    /// points to an occupied or retired slot, two keys share an index or a key is older than
+    /// the generation its vacant slot carries. Vacant slots take the generation of the key, which
+    /// can only move them forward, so stale keys stay invalid.
+    pub fn extend_exact<I: IntoIterator<Item = (GenIndex, T)>>(
+        &mut self,
+        iter: I,
+    ) -> Result<(), Error> {
+        let items: Vec<_> = iter.into_iter().collect();
+
+        let mut seen = HashSet::with_capacity(items.len());
+        for (key, _) in items.iter() {
+            if self.value_at(key.index).is_some() {
+                bail!("GenIndexAllocator::extend_exact: Index is occupied");
+            }
+            if self.is_retired(key.index) {
+                bail!("GenIndexAllocator::extend_exact: Slot is retired");
+            }
+            if self
+                .generation_at(key.index)
+                .is_some_and(|generation| key.generation < generation)
+            {
+                bail!("GenIndexAllocator::extend_exact: Generation of key is older than the slot");
+            }
+            if !seen.insert(key.index) {
+                bail!("GenIndexAllocator::extend_exact: Duplicate index in keys");
+            }
+            self.check_index(key.index)?;
+        }
+
+        for (key, value) in items {
+            self.ensure_index(key.index)?;
+            if let Some(position) = self
+                .free_indices
+                .iter()
+                .rposition(|&free_idx| free_idx == key.index)
+            {
+                self.free_indices.remove(position);
+            }
+            self.slots[key.index].generation = key.generation;
+            self.fill(key.index, value);
+            self.num_occupied += 1;
+            self.record_change(key, ChangeKind::Added);
+        }
+        Ok(())
+    }
+
+    /// Take the vacant slot at `index` off the free list before storing a value in it
+    ///
+    /// Vacant slots that are not on the free list are reserved and can be claimed as well, unless
+    /// they are retired. `method` names the public method in the error.
+    fn claim_vacant(&mut self, index: usize, method: &str) -> Result<(), Error> {
+        match self
+            .free_indices
+            .iter()
+            .rposition(|&free_idx| free_idx == index)
+        {
+            Some(position) => {
+                self.free_indices.remove(position);
+            }
+            None => {
+                if self.is_retired(index) {
+                    bail!("GenIndexAllocator::{}: Slot is retired", method);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether the slot at `index` is vacant at the last generation and will never be reused
+    fn is_retired(&self, index: usize) -> bool {
+        match self.slots.get(index) {
+            None => false,
+            Some(slot) => {
+                !slot.occupied
+                    && slot.generation == u32::MAX
+                    && matches!(
+                        self.overflow_policy,
+                        OverflowPolicy::Error | OverflowPolicy::Retire
+                    )
+                    && !self.free_indices.contains(&index)
+            }
+        }
+    }
+
+    /// Apply `set` for every update and collect the individual results in order
+    pub fn bulk_set<I: IntoIterator<Item = (GenIndex, T)>>(
+        &mut self,
+        updates: I,
+    ) -> Vec<Result<T, Error>> {
+        updates
+            .into_iter()
+            .map(|(key, value)| self.set(&key, value))
+            .collect()
+    }
+
+    /// Remove the value at `index` and move the highest-indexed live value into its slot
+    ///
+    /// Returns the removed value and, if a value was moved, its new key. Both the key of the
+    /// removed value and the old key of the moved value are invalid afterwards. Returns `None` if
+    /// `index` is out of range or the slot is vacant.
+    pub fn swap_remove_index(&mut self, index: usize) -> Option<(T, Option<GenIndex>)> {
+        self.value_at(index)?;
+
+        let last_live_idx = self
+            .slots
+            .iter()
+            .rposition(|slot| slot.occupied)
+            .filter(|&last_idx| last_idx > index);
+        let next_generation = self.next_generation(self.slots[index].generation);
+
+        match (last_live_idx, next_generation) {
+            (Some(last_idx), Some(generation)) => {
+                let moved = self
+                    .release(last_idx)
+                    .expect("Last live slot should be occupied");
+
+                let old_key = self.key_at(index);
+                let removed = self.fill(index, moved);
+                self.slots[index].generation = generation;
+                let new_key = self.key_at(index);
+                #[cfg(feature = "reuse-log")]
+                self.reuse_log.push((index, old_key.generation, generation));
+                self.notify_free(index);
+                self.record_change(old_key, ChangeKind::Removed);
+                self.record_change(new_key, ChangeKind::Added);
+                removed.map(|removed| (removed, Some(new_key)))
+            }
+            // Nothing to move, or the slot is retired and cannot take the moved value
+            _ => self.release(index).map(|removed| (removed, None)),
+        }
+    }
+
+    /// Like `swap_remove_index`, but makes sure the freed slot appears only once on the free list
+    ///
+    /// Older copies of the freed index on a corrupted free list would hand the slot out twice, so
+    /// they are removed.
+    pub fn swap_remove_return_moved_key(&mut self, index: usize) -> Option<(T, Option<GenIndex>)> {
+        let num_free_before = self.free_indices.len();
+        let result = self.swap_remove_index(index)?;
+
+        if self.free_indices.len() > num_free_before {
+            let last_position = self.free_indices.len() - 1;
+            let freed = self.free_indices[last_position];
+            let mut position = 0;
+            self.free_indices.retain(|&free_idx| {
+                let keep = free_idx != freed || position == last_position;
+                position += 1;
+                keep
+            });
+        }
+        Some(result)
+    }
+
+    /// Set the generation of the slot at `index` directly, e.g. to test generation overflow
+    ///
+    /// Panics if `index` is out of range.
+    #[cfg(any(test, feature = "testing"))]
+    pub fn force_generation(&mut self, index: usize, generation: u32) {
+        self.slots[index].generation = generation;
+    }
+
+    /// Current generation of the slot at `index`, if the index is in range
+    pub fn generation_at(&self, index: usize) -> Option<u32> {
+        self.slots.get(index).map(|slot| slot.generation)
+    }
+
+    /// Largest generation among all live entries, `None` if there are none
+    ///
+    /// Useful to pick the narrowest generation type the current keys fit into.
+    pub fn max_live_generation(&self) -> Option<u32> {
+        self.keys().map(|key| key.generation).max()
+    }
+
+    /// Current generation of the slot `key` points to, if the index is in range
+    ///
+    /// Since the generation is bumped whenever a slot is freed, `live - key.generation()` is the
+    /// number of times the slot was freed since the key was handed out.
+    pub fn live_generation_for(&self, key: &GenIndex) -> Option<u32> {
+        self.generation_at(key.index)
+    }
+
+    /// Current key of the slot at `index` if it is occupied
+    ///
+    /// The returned key belongs to whatever value lives in the slot now, which is not necessarily
+    /// the value a stale key for the same index referred to.
+    pub fn current_key_at(&self, index: usize) -> Option<GenIndex> {
+        self.slots
+            .get(index)
+            .filter(|slot| slot.occupied)
+            .map(|slot| GenIndex {
+                index,
+                generation: slot.generation,
+            })
+    }
+
+    /// Repair the free list by removing duplicates and indices of occupied or missing slots
+    ///
+    /// Older versions could add an index to the free list twice when deallocating the same key
+    /// twice. Returns the number of removed free list entries.
+    pub fn trim_free_list_duplicates(&mut self) -> usize {
+        let num_free_before = self.free_indices.len();
+        let mut seen = vec![false; self.slots.len()];
+        let slots = &self.slots;
+        self.free_indices.retain(|&index| match slots.get(index) {
+            Some(slot) if !slot.occupied && !seen[index] => {
+                seen[index] = true;
+                true
+            }
+            _ => false,
+        });
+        num_free_before - self.free_indices.len()
+    }
+
+    pub fn capacity_report(&self) -> CapacityReport {
+        let slots = self.slots.len();
+        let free = self.free_indices.len();
+        CapacityReport {
+            slots,
+            occupied: self.num_occupied,
+            free,
+            entries_capacity: self.slots.capacity(),
+            free_capacity: self.free_indices.capacity(),
+            fragmentation: if slots == 0 {
+                0.0
+            } else {
+                free as f64 / slots as f64
+            },
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.num_occupied
+    }
+
+    /// Number of occupied entries in O(1)
+    ///
+    /// In debug builds, this checks the maintained counter against the actual number of
+    /// occupied entries.
+    pub fn count(&self) -> usize {
+        debug_assert_eq!(
+            self.num_occupied,
+            self.iter().count(),
+            "GenIndexAllocator::count: Occupied counter out of sync"
+        );
+        self.num_occupied
+    }
+
+    /// Recount the occupied entries, store the result as the maintained counter and return it
+    ///
+    /// Repairs `len` after the counter got out of sync.
+    pub fn recompute_occupied_count(&mut self) -> usize {
+        self.num_occupied = self.iter().count();
+        self.num_occupied
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    pub fn contains(&self, key: &GenIndex) -> bool {
+        self.slots
+            .get(key.index)
+            .is_some_and(|slot| slot.occupied && slot.generation == key.generation)
+    }
+
+    /// Whether all keys are valid, `true` for an empty slice
+    pub fn contains_all(&self, keys: &[GenIndex]) -> bool {
+        keys.iter().all(|key| self.contains(key))
+    }
+
+    /// Whether at least one key is valid, `false` for an empty slice
+    pub fn contains_any(&self, keys: &[GenIndex]) -> bool {
+        keys.iter().any(|key| self.contains(key))
+    }
+
+    /// Whether there are no live entries, regardless of slots and capacity
+    ///
+    /// Same as `is_empty`, based on the maintained counter.
+    pub fn has_no_live_entries(&self) -> bool {
+        self.num_occupied == 0
+    }
+
+    /// Whether every slot is vacant, checked by scanning all slots
+    pub fn all_slots_vacant(&self) -> bool {
+        self.slots.iter().all(|slot| !slot.occupied)
+    }
+
+    /// Whether every key yielded by `keys` is valid, `true` for no keys
+    pub fn covers(&self, mut keys: impl Iterator<Item = GenIndex>) -> bool {
+        keys.all(|key| self.contains(&key))
+    }
+
+    /// Keys of all occupied entries, e.g. to compare against the keys of a `SecondaryMap`
+    pub fn live_key_set(&self) -> HashSet<GenIndex> {
+        self.keys().collect()
+    }
+
+    /// `SecondaryMap` with a unit value for every live key, usable as a set of the current keys
+    ///
+    /// The map does not follow later changes of the allocator.
+    pub fn to_key_set(&self) -> SecondaryMap<()> {
+        let mut key_set = SecondaryMap::with_capacity(self.slots.len());
+        for key in self.keys() {
+            key_set.insert(key, ());
+        }
+        key_set
+    }
+
+    /// Deallocate all entries, keeping the slots and capacity for reuse
+    ///
+    /// Generations of occupied slots are bumped, so their keys are invalid afterwards. See
+    /// `clear_keep_generations` for keeping them. Vacant slots are left as they are, so keys of
+    /// reserved slots from `reserve_contiguous` or `ensure_index` can still be filled with `put`.
+    pub fn clear(&mut self) {
+        for index in 0..self.slots.len() {
+            self.release(index);
+        }
+    }
+
+    /// Deallocate all entries without bumping generations
+    ///
+    /// Keys handed out before stay valid for their now vacant slots and can be filled again with
+    /// `put`. Because the slots go back on the free list with the same generation, `allocate` may
+    /// also hand out a key equal to an old one, so old keys can alias new values. Only use this if
+    /// all holders of old keys expect that. Like with `clear`, reserved slots stay reserved and
+    /// their keys stay valid.
+    pub fn clear_keep_generations(&mut self) {
+        for index in 0..self.slots.len() {
+            if self.take(index).is_some() {
+                self.num_occupied -= 1;
+                self.push_free(index);
+                self.notify_free(index);
+                let key = self.key_at(index);
+                self.record_change(key, ChangeKind::Removed);
+            }
+        }
+    }
+
+    /// Take all live `(key, value)` pairs out in index order, leaving the allocator empty
+    ///
+    /// Like `clear`, the slots and capacity are kept for reuse and the returned keys are invalid
+    /// afterwards.
+    pub fn drain_to_vec(&mut self) -> Vec<(GenIndex, T)> {
+        let mut drained = Vec::with_capacity(self.num_occupied);
+        for index in 0..self.slots.len() {
+            let key = self.key_at(index);
+            if let Some(value) = self.release(index) {
+                drained.push((key, value));
+            }
+        }
+        drained
+    }
+
+    /// Deallocate all entries in the slot index `range`, clamped to the existing slots
+    pub fn clear_range(&mut self, range: Range<usize>) {
+        let end = range.end.min(self.slots.len());
+        for index in range.start.min(end)..end {
+            self.release(index);
+        }
+    }
+
+    /// Deallocate all entries for which `f` returns `false`
+    pub fn retain<F: FnMut(GenIndex, &T) -> bool>(&mut self, mut f: F) {
+        for index in 0..self.slots.len() {
+            let keep = match self.value_at(index) {
+                None => true,
+                Some(value) => f(self.key_at(index), value),
+            };
+            if !keep {
+                self.release(index);
+            }
+        }
+    }
+
+    /// Iterate over all occupied entries in index order
+    pub fn iter(&self) -> impl Iterator<Item = (GenIndex, &T)> {
+        self.keys()
+            .filter_map(|key| self.values[key.index].as_ref().map(|value| (key, value)))
+    }
+
+    /// Iterate over every slot as `(index, generation, value)`, including vacant slots
+    ///
+    /// For vacant slots, the generation is the one the next value in the slot will get.
+    pub fn iter_entries_raw(&self) -> impl Iterator<Item = (usize, u32, Option<&T>)> {
+        self.slots
+            .iter()
+            .enumerate()
+            .map(|(index, slot)| (index, slot.generation, self.value_at(index)))
+    }
+
+    /// Keys of all occupied entries in index order, packed with `GenIndex::to_bits`
+    ///
+    /// Panics if an index does not fit into 32 bits.
+    pub fn packed_keys(&self) -> impl Iterator<Item = u64> + '_ {
+        self.keys().map(|key| key.to_bits())
+    }
+
+    /// Iterate over the keys of all occupied entries in index order
+    pub fn keys(&self) -> impl Iterator<Item = GenIndex> + '_ {
+        self.slots
+            .iter()
+            .enumerate()
+            .filter(|(_, slot)| slot.occupied)
+            .map(|(index, slot)| GenIndex {
+                index,
+                generation: slot.generation,
+            })
+    }
+
+    /// Iterate over the keys of all occupied entries in strictly ascending index order
+    ///
+    /// Unlike `keys`, the order is part of the contract and can be relied on for deterministic
+    /// output.
+    pub fn iter_keys_sorted(&self) -> impl Iterator<Item = GenIndex> + '_ {
+        // Slots are stored by index, so iterating them is already sorted
+        self.keys()
+    }
+
+    /// Keys of all occupied entries for which `pred` returns `true`, in index order
+    ///
+    /// Useful to mutate the matching entries with `get_mut` in a second pass.
+    pub fn keys_where<F: FnMut(GenIndex, &T) -> bool>(&self, mut pred: F) -> Vec<GenIndex> {
+        self.iter()
+            .filter(|&(key, value)| pred(key, value))
+            .map(|(key, _)| key)
+            .collect()
+    }
+
+    /// Fold over all occupied entries in index order
+    pub fn fold_values<B, F: FnMut(B, GenIndex, &T) -> B>(&self, init: B, mut f: F) -> B {
+        self.iter()
+            .fold(init, |acc, (key, value)| f(acc, key, value))
+    }
+
+    /// Split the allocator into one with all entries for which `f` returns `true` and one with
+    /// the rest
+    ///
+    /// The returned map resolves every old key to whether its entry went into the first
+    /// allocator and its key in that allocator.
+    pub fn partition<F: FnMut(GenIndex, &T) -> bool>(
+        self,
+        mut f: F,
+    ) -> (Self, Self, HashMap<GenIndex, (bool, GenIndex)>) {
+        let mut matching = Self::with_capacity(self.num_occupied);
+        let mut rest = Self::with_capacity(self.num_occupied);
+        let mut remap = HashMap::with_capacity(self.num_occupied);
+
+        for (index, (slot, value)) in self.slots.into_iter().zip(self.values).enumerate() {
+            let key = GenIndex {
+                index,
+                generation: slot.generation,
+            };
+            if let Some(value) = value.filter(|_| slot.occupied) {
+                let is_match = f(key, &value);
+                let target = if is_match { &mut matching } else { &mut rest };
+                let new_key = target
+                    .allocate(value)
+                    .expect("Allocating in a fresh allocator should not fail");
+                remap.insert(key, (is_match, new_key));
+            }
+        }
+
+        (matching, rest, remap)
+    }
+
+    /// Iterate mutably over the occupied entries for which `pred` returns `true`
+    pub fn iter_mut_where<F: FnMut(GenIndex, &T) -> bool>(
+        &mut self,
+        mut pred: F,
+    ) -> impl Iterator<Item = (GenIndex, &mut T)> {
+        self.live_mut()
+            .filter(move |(key, value)| pred(*key, value))
+    }
+
+    /// Mutate every occupied entry in place
+    pub fn update_all<F: FnMut(GenIndex, &mut T)>(&mut self, mut f: F) {
+        for (key, value) in self.live_mut() {
+            f(key, value);
+        }
+    }
+
+    /// Mutate the entries of `keys` in ascending index order, each entry at most once
+    ///
+    /// Stale keys are skipped. Returns the number of entries `f` was applied to.
+    pub fn mutate_keys<F: FnMut(GenIndex, &mut T)>(
+        &mut self,
+        keys: &[GenIndex],
+        mut f: F,
+    ) -> usize {
+        let mut sorted = keys.to_vec();
+        sorted.sort_unstable_by_key(|key| (key.index, key.generation));
+        sorted.dedup();
+
+        let mut num_applied = 0;
+        for key in sorted {
+            if let Some(value) = self.get_mut(&key) {
+                f(key, value);
+                num_applied += 1;
+            }
+        }
+        num_applied
+    }
+
+    /// Mutate every occupied entry in parallel, splitting them into chunks of at least
+    /// `min_chunk` occupied entries
+    ///
+    /// Larger chunks lower the scheduling overhead for cheap `f`. The occupied entries are
+    /// collected first, so vacant slots of a fragmented allocator do not shrink the chunks.
+    #[cfg(feature = "rayon")]
+    pub fn par_for_each_mut<F: Fn(GenIndex, &mut T) + Sync>(&mut self, min_chunk: usize, f: F)
+    where
+        T: Send,
+    {
+        use rayon::prelude::*;
+
+        let mut live: Vec<(GenIndex, &mut T)> = self.live_mut().collect();
+        live.par_iter_mut()
+            .with_min_len(min_chunk.max(1))
+            .for_each(|(key, value)| f(*key, value));
+    }
+
+    /// Replace every occupied entry with a new value computed from the old one
+    pub fn map_in_place<F: FnMut(GenIndex, &T) -> T>(&mut self, mut f: F) {
+        let mut change_log = self.change_log.take();
+        for (key, value) in self.live_mut() {
+            *value = f(key, value);
+            if let Some(change_log) = change_log.as_mut() {
+                change_log.record(key, ChangeKind::Modified);
+            }
+        }
+        self.change_log = change_log;
+    }
+
+    /// Keys of all occupied entries, stably sorted by a key derived from their values
+    ///
+    /// Entries with equal sort keys stay in index order.
+    pub fn sorted_keys_by<K: Ord, F: FnMut(&T) -> K>(&self, mut f: F) -> Vec<GenIndex> {
+        let mut keyed: Vec<_> = self.iter().map(|(key, value)| (f(value), key)).collect();
+        keyed.sort_by(|a, b| a.0.cmp(&b.0));
+        keyed.into_iter().map(|(_, key)| key).collect()
+    }
+
+    /// Swap all entries and slot state with `other`, keeping the configuration of both
+    ///
+    /// Keys follow the data: after the swap, a key handed out by `self` is only valid for
+    /// `other` and vice versa. Hooks, overflow policy and auto-compaction settings stay with
+    /// their allocator. The index cap moves with the contents, so that no allocator ends up with
+    /// slots above its cap.
+    ///
+    /// The recorded changes and the reuse logs of both allocators are cleared, since they refer to
+    /// the data that moved away. The swap itself is not recorded: a `ChangeCursor` keeps working,
+    /// but only reports changes made after the swap, so state derived from the change log has to
+    /// be rebuilt.
+    pub fn swap_contents(&mut self, other: &mut Self) {
+        std::mem::swap(&mut self.slots, &mut other.slots);
+        std::mem::swap(&mut self.values, &mut other.values);
+        std::mem::swap(&mut self.free_indices, &mut other.free_indices);
+        std::mem::swap(&mut self.num_occupied, &mut other.num_occupied);
+        std::mem::swap(&mut self.generation_floor, &mut other.generation_floor);
+        std::mem::swap(&mut self.max_index, &mut other.max_index);
+        for allocator in [self, other] {
+            if let Some(change_log) = allocator.change_log.as_mut() {
+                change_log.changes.clear();
+            }
+            #[cfg(feature = "reuse-log")]
+            allocator.reuse_log.clear();
+        }
+    }
+
+    /// Borrow the allocator as a read-only view
+    ///
+    /// The view can be handed out across API boundaries to give read access without exposing
+    /// any of the mutating methods.
+    pub fn view(&self) -> ArenaView<'_, T> {
+        ArenaView { allocator: self }
+    }
+}
+
+impl<T: std::fmt::Debug> GenIndexAllocator<T> {
+    /// Write the full state of every slot and the free list to `w`, one line per slot
+    pub fn debug_dump<W: std::fmt::Write>(&self, w: &mut W) -> std::fmt::Result {
+        for (index, generation, value) in self.iter_entries_raw() {
+            match value {
+                None => writeln!(w, "slot {}: generation {}, vacant", index, generation)?,
+                Some(value) => writeln!(
+                    w,
+                    "slot {}: generation {}, occupied: {:?}",
+                    index, generation, value
+                )?,
+            }
+        }
+        writeln!(w, "free list: {:?}", self.free_indices)
+    }
+}
+
+impl<T: Default> GenIndexAllocator<T> {
+    /// Mutable reference to the value of `key`, inserting `T::default()` if the key is valid but
+    /// its slot is vacant
+    ///
+    /// Only stale and out-of-range keys are rejected, like in `put`.
+    pub fn get_mut_or_insert_default(&mut self, key: &GenIndex) -> Result<&mut T, Error> {
+        match self.slots.get(key.index) {
+            None => bail!("GenIndexAllocator::get_mut_or_insert_default: Index not found"),
+            Some(slot) => {
+                if slot.generation != key.generation {
+                    bail!("GenIndexAllocator::get_mut_or_insert_default: Wrong generation");
+                }
+                if !slot.occupied {
+                    self.claim_vacant(key.index, "get_mut_or_insert_default")?;
+                    self.num_occupied += 1;
+                    self.record_change(*key, ChangeKind::Added);
+                    self.fill(key.index, Default::default());
+                }
+
+                Ok(self
+                    .value_at_mut(key.index)
+                    .expect("Slot should be occupied"))
+            }
+        }
+    }
+}
+
+impl<T: Clone> GenIndexAllocator<T> {
+    /// Create an allocator holding `count` clones of `value` and return their keys
+    pub fn with_prefilled(count: usize, value: T) -> (Self, Vec<GenIndex>) {
+        let mut allocator = Self::with_capacity(count);
+        allocator.slots.resize(
+            count,
+            Slot {
+                generation: 0,
+                occupied: true,
+            },
+        );
+        allocator.values.resize(count, Some(value));
+        let keys = (0..count)
+            .map(|index| GenIndex {
+                index,
+                generation: 0,
+            })
+            .collect();
+        allocator.num_occupied = count;
+        (allocator, keys)
+    }
+
+    /// Append one slot per value, filled with clones of `values`, and return their keys with
+    /// consecutive indices
+    ///
+    /// Like `reserve_contiguous`, the free list is bypassed on purpose so that the slots are
+    /// adjacent, and nothing is allocated if the last index would be above the index cap.
+    pub fn allocate_slice(&mut self, values: &[T]) -> Result<Vec<GenIndex>, Error> {
+        if !values.is_empty() {
+            self.check_index(self.slots.len().saturating_add(values.len() - 1))?;
+        }
+        values
+            .iter()
+            .map(|value| {
+                let key = self.push_slot(Some(value.clone()))?;
+                self.num_occupied += 1;
+                self.record_change(key, ChangeKind::Added);
+                Ok(key)
+            })
+            .collect()
+    }
+
+    /// Snapshot of all live keys and cloned values in index order
+    ///
+    /// The two vectors have the same length and the key at position `i` belongs to the value at
+    /// position `i`.
+    pub fn to_packed(&self) -> (Vec<GenIndex>, Vec<T>) {
+        self.iter().map(|(key, value)| (key, value.clone())).unzip()
+    }
+}
+
+/// Immutable view into a `GenIndexAllocator`, see `GenIndexAllocator::view`
+pub struct ArenaView<'a, T> {
+    allocator: &'a GenIndexAllocator<T>,
+}
+
+impl<'a, T> ArenaView<'a, T> {
+    pub fn get(&self, key: &GenIndex) -> Option<&'a T> {
+        self.allocator.get(key)
+    }
+
+    pub fn contains(&self, key: &GenIndex) -> bool {
+        self.allocator.contains(key)
+    }
+
+    pub fn len(&self) -> usize {
+        self.allocator.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.allocator.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (GenIndex, &'a T)> {
+        self.allocator.iter()
+    }
+}
+
+impl<'a, T> Clone for ArenaView<'a, T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T> Copy for ArenaView<'a, T> {}
+
+/// Guard for a reserved slot, see `GenIndexAllocator::begin_reserve`
+pub struct ReservedSlot<'a, T> {
+    allocator: &'a mut GenIndexAllocator<T>,
+    index: usize,
+    committed: bool,
+}
+
+impl<'a, T> ReservedSlot<'a, T> {
+    /// Key the value will get when committed
+    pub fn key(&self) -> GenIndex {
+        self.allocator.key_at(self.index)
+    }
+
+    /// Store `value` in the reserved slot and return its key
+    pub fn commit(mut self, value: T) -> GenIndex {
+        self.allocator.fill(self.index, value);
+        let key = self.allocator.key_at(self.index);
+        self.allocator.num_occupied += 1;
+        self.allocator.record_change(key, ChangeKind::Added);
+        self.committed = true;
+        key
+    }
+}
+
+impl<'a, T> Drop for ReservedSlot<'a, T> {
+    fn drop(&mut self) {
+        if self.committed {
+            return;
+        }
+
+        // Roll back the reservation, invalidating the key it handed out. Drop must not panic, so
+        // apart from `Wrap`, a slot at the generation ceiling is retired under every policy.
+        let allocator = &mut *self.allocator;
+        let old_generation = allocator.slots[self.index].generation;
+        let next_generation = match allocator.overflow_policy {
+            OverflowPolicy::Wrap => Some(old_generation.wrapping_add(1)),
+            _ => old_generation.checked_add(1),
+        };
+        if let Some(generation) = next_generation {
+            allocator.slots[self.index].generation = generation;
+            #[cfg(feature = "reuse-log")]
+            allocator
+                .reuse_log
+                .push((self.index, old_generation, generation));
+            allocator.push_free(self.index);
+        }
+    }
+}
+
+impl<T> Default for GenIndexAllocator<T> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Same as `GenIndexAllocator::from_options`, every slot starts at generation 0
+impl<T> From<Vec<Option<T>>> for GenIndexAllocator<T> {
+    fn from(values: Vec<Option<T>>) -> Self {
+        Self::from_options(values)
+    }
+}
+
+/// Positional values of all slots, the generations are lost
+impl<T> From<GenIndexAllocator<T>> for Vec<Option<T>> {
+    fn from(allocator: GenIndexAllocator<T>) -> Self {
+        allocator.values
+    }
+}
+
+/// Rewrite every key in `map` with a remap returned by e.g. `GenIndexAllocator::compact`
+///
+/// Entries whose key is not in the remap belong to removed values and are dropped.
+pub fn remap_external<K: Eq + std::hash::Hash>(
+    map: &mut HashMap<K, GenIndex>,
+    remap: &HashMap<GenIndex, GenIndex>,
+) {
+    map.retain(|_, key| match remap.get(key) {
+        Some(new_key) => {
+            *key = *new_key;
+            true
+        }
+        None => false,
+    });
+}
+
+/// Slot generations, values, free list, overflow policy and generation floor are serialized
+///
+/// Hooks, auto-compaction and the index cap are configuration and not part of the data.
+#[cfg(feature = "serde")]
+impl<T: serde::Serialize> serde::Serialize for GenIndexAllocator<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        let generations: Vec<u32> = self.slots.iter().map(|slot| slot.generation).collect();
+        let mut state = serializer.serialize_struct("GenIndexAllocator", 5)?;
+        state.serialize_field("generations", &generations)?;
+        state.serialize_field("values", &self.values)?;
+        state.serialize_field("free_indices", &self.free_indices)?;
+        state.serialize_field("overflow_policy", &self.overflow_policy)?;
+        state.serialize_field("generation_floor", &self.generation_floor)?;
+        state.end()
+    }
+}
+
+/// Rejects data that would leave the allocator inconsistent
+///
+/// There has to be one value per generation and the free list may only contain distinct indices
+/// of vacant slots.
+#[cfg(feature = "serde")]
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for GenIndexAllocator<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        use serde::de::Error as _;
+
+        #[derive(serde::Deserialize)]
+        #[serde(rename = "GenIndexAllocator")]
+        struct Data<T> {
+            generations: Vec<u32>,
+            values: Vec<Option<T>>,
+            free_indices: Vec<usize>,
+            overflow_policy: OverflowPolicy,
+            generation_floor: u32,
+        }
+
+        let data = Data::<T>::deserialize(deserializer)?;
+        if data.generations.len() != data.values.len() {
+            return Err(D::Error::custom(format!(
+                "GenIndexAllocator::deserialize: {} generations but {} values",
+                data.generations.len(),
+                data.values.len()
+            )));
+        }
+        let slots: Vec<Slot> = data
+            .generations
+            .iter()
+            .zip(data.values.iter())
+            .map(|(&generation, value)| Slot {
+                generation,
+                occupied: value.is_some(),
+            })
+            .collect();
+
+        let mut is_free = vec![false; slots.len()];
+        for &free_idx in data.free_indices.iter() {
+            match slots.get(free_idx) {
+                None => {
+                    return Err(D::Error::custom(format!(
+                        "GenIndexAllocator::deserialize: Free index {} is out of range",
+                        free_idx
+                    )))
+                }
+                Some(slot) if slot.occupied => {
+                    return Err(D::Error::custom(format!(
+                        "GenIndexAllocator::deserialize: Free index {} points to an occupied slot",
+                        free_idx
+                    )))
+                }
+                Some(_) if is_free[free_idx] => {
+                    return Err(D::Error::custom(format!(
+                        "GenIndexAllocator::deserialize: Free index {} is listed twice",
+                        free_idx
+                    )))
+                }
+                Some(_) => is_free[free_idx] = true,
+            }
+        }
+
+        let num_occupied = slots.iter().filter(|slot| slot.occupied).count();
+        Ok(Self {
+            slots,
+            values: data.values,
+            free_indices: data.free_indices,
+            num_occupied,
+            overflow_policy: data.overflow_policy,
+            generation_floor: data.generation_floor,
+            ..Self::new()
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_create_with_capacity() -> Result<(), Error> {
+        let capacity = 200;
+        let gen_alloc = GenIndexAllocator::<i32>::with_capacity(capacity);
+        assert_eq!(gen_alloc.slots.capacity(), capacity);
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_and_get() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+
+        // Create value and check it
+        let value1 = 1i32;
+        let key1 = gen_alloc.allocate(value1)?;
+        assert_eq!(gen_alloc.slots.len(), 1);
+        assert_eq!(gen_alloc.get(&key1), Some(&value1));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_and_set() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+
+        // Create value and check it
+        let value1 = 1i32;
+        let key1 = gen_alloc.allocate(value1)?;
+        assert_eq!(gen_alloc.slots.len(), 1);
+        assert_eq!(gen_alloc.get(&key1), Some(&value1));
+
+        // Create value and check it
+        let value2 = 2i32;
+        let key2 = gen_alloc.allocate(value2)?;
+        assert_eq!(gen_alloc.slots.len(), 2);
+        assert_eq!(gen_alloc.get(&key2), Some(&value2));
+
+        // Set first key to different value - the second value should be unchanged
+        let new_value1 = 99i32;
+        gen_alloc.set(&key1, new_value1)?;
+        assert_eq!(gen_alloc.slots.len(), 2);
+        assert_eq!(gen_alloc.get(&key1), Some(&new_value1));
+        assert_eq!(gen_alloc.get(&key2), Some(&value2));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_does_not_fill_vacant_slot() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let key = gen_alloc.allocate(1)?;
+        gen_alloc.deallocate(&key)?;
+
+        assert!(gen_alloc.set(&key, 2).is_err());
+        assert_eq!(gen_alloc.get(&key), None, "Vacant slot must stay vacant");
+        assert_eq!(gen_alloc.count(), 0);
+
+        // The slot is still free and handed out normally
+        let reused = gen_alloc.allocate(3)?;
+        assert_eq!(reused.index, key.index);
+        assert_eq!(gen_alloc.get(&reused), Some(&3));
+        assert_eq!(gen_alloc.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reuse_free_indices() -> Result<(), Error> {
+        let capacity = 5;
+        let mut gen_alloc = GenIndexAllocator::with_capacity(capacity);
+        assert_eq!(gen_alloc.slots.len(), 0);
+        assert_eq!(gen_alloc.slots.capacity(), capacity);
+
+        let mut alloced_keys: Vec<_> = (0..capacity)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+
+        // We created the values in order - check that each key points to the right value
+        for (value, key) in alloced_keys.iter().enumerate() {
+            assert_eq!(gen_alloc.get(key), Some(&value));
+        }
+
+        // Split the keys and free some of them
+        let num_keys_to_free = 2;
+        let to_free = alloced_keys.split_off(capacity - num_keys_to_free);
+
+        for key in to_free.iter() {
+            gen_alloc.deallocate(key)?;
+        }
+
+        assert_eq!(
+            gen_alloc.slots.len(),
+            capacity,
+            "We do not remove entries so the length should be unchanged"
+        );
+        assert_eq!(
+            gen_alloc.slots.capacity(),
+            capacity,
+            "We do not exceed capacity so it should be unchanged"
+        );
+
+        // Reuse indices, the capacity should be unchanged but old keys should get invalid
+        let reused_entries_keys: Vec<_> = (0..num_keys_to_free)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+
+        assert_eq!(
+            gen_alloc.slots.len(),
+            capacity,
+            "We do not remove entries so the length should be unchanged"
+        );
+        assert_eq!(
+            gen_alloc.slots.capacity(),
+            capacity,
+            "We do not exceed capacity so it should be unchanged"
+        );
+
+        for key in to_free.iter() {
+            assert_eq!(
+                gen_alloc.get(key),
+                None,
+                "This key should be invalid because the generation does not match"
+            );
+        }
+
+        for (value, key) in reused_entries_keys.iter().enumerate() {
+            assert_eq!(gen_alloc.get(key), Some(&value));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_view_matches_allocator() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..5)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+
+        let view = gen_alloc.view();
+        assert_eq!(view.len(), gen_alloc.len());
+        assert_eq!(view.len(), 4);
+
+        for key in keys.iter() {
+            assert_eq!(view.get(key), gen_alloc.get(key));
+            assert_eq!(view.contains(key), gen_alloc.contains(key));
+        }
+        assert!(!view.contains(&keys[1]));
+
+        let view_entries: Vec<_> = view
+            .iter()
+            .map(|(key, value)| (key.index, *value))
+            .collect();
+        let alloc_entries: Vec<_> = gen_alloc
+            .iter()
+            .map(|(key, value)| (key.index, *value))
+            .collect();
+        assert_eq!(view_entries, alloc_entries);
+        assert_eq!(view_entries, vec![(0, 0), (2, 2), (3, 3), (4, 4)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_remove_index() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..4)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+
+        // Removing from the middle moves the last live value into the hole
+        let (removed, moved_key) = gen_alloc.swap_remove_index(1).expect("Should remove");
+        assert_eq!(removed, 1);
+        let moved_key = moved_key.expect("The last value should have been moved");
+        assert_eq!(moved_key.index, 1);
+        assert_eq!(gen_alloc.get(&moved_key), Some(&3));
+        assert_eq!(
+            gen_alloc.get(&keys[1]),
+            None,
+            "Removed key should be invalid"
+        );
+        assert_eq!(
+            gen_alloc.get(&keys[3]),
+            None,
+            "Old key of moved value should be invalid"
+        );
+        assert_eq!(gen_alloc.len(), 3);
+
+        // Removing the last live value moves nothing
+        let (removed, moved_key) = gen_alloc.swap_remove_index(2).expect("Should remove");
+        assert_eq!(removed, 2);
+        assert_eq!(moved_key, None);
+        assert_eq!(gen_alloc.len(), 2);
+
+        // Vacant and out-of-range indices are rejected
+        assert!(gen_alloc.swap_remove_index(3).is_none());
+        assert!(gen_alloc.swap_remove_index(10).is_none());
+
+        // Freed slots are reused
+        let key = gen_alloc.allocate(99)?;
+        assert!(key.index == 2 || key.index == 3);
+        assert_eq!(gen_alloc.get(&key), Some(&99));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_count() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        assert_eq!(gen_alloc.count(), 0);
+
+        let keys: Vec<_> = (0..5)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        assert_eq!(gen_alloc.count(), 5);
+
+        gen_alloc.deallocate(&keys[0])?;
+        gen_alloc.deallocate(&keys[3])?;
+        assert_eq!(gen_alloc.count(), 3);
+
+        // Deallocating twice must fail and not change the count again
+        assert!(gen_alloc.deallocate(&keys[3]).is_err());
+        assert_eq!(gen_alloc.count(), 3);
+        assert_eq!(gen_alloc.free_indices.len(), 2);
+
+        gen_alloc.allocate(10)?;
+        assert_eq!(gen_alloc.count(), 4);
+
+        gen_alloc.clear();
+        assert_eq!(gen_alloc.count(), 0);
+        assert_eq!(gen_alloc.len(), 0);
+        assert_eq!(gen_alloc.free_indices.len(), 5);
+        for key in keys.iter() {
+            assert!(!gen_alloc.contains(key));
+        }
+
+        gen_alloc.allocate(11)?;
+        assert_eq!(gen_alloc.count(), 1);
+        assert_eq!(gen_alloc.slots.len(), 5, "Cleared slots should be reused");
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_generation_bumped_on_free() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(2);
+        let key = gen_alloc.allocate(1)?;
+        gen_alloc.deallocate(&key)?;
+
+        // The vacant slot already carries the generation of its next value
+        assert_eq!(gen_alloc.slots[key.index].generation, key.generation + 1);
+        assert!(gen_alloc.get(&key).is_none());
+        assert!(
+            gen_alloc.deallocate(&key).is_err(),
+            "The freed key is stale right away"
+        );
+        assert_eq!(gen_alloc.free_indices, vec![key.index]);
+
+        let reused = gen_alloc.allocate(2)?;
+        assert_eq!(reused.index, key.index);
+        assert_eq!(reused.generation, key.generation + 1);
+        assert!(gen_alloc.get(&key).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retain() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+
+        gen_alloc.retain(|_, value| value % 2 == 0);
+        assert_eq!(gen_alloc.count(), 3);
+        for (value, key) in keys.iter().enumerate() {
+            assert_eq!(gen_alloc.contains(key), value % 2 == 0);
+        }
+        assert_eq!(gen_alloc.free_indices.len(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_bulk_set() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let key1 = gen_alloc.allocate(1)?;
+        let key2 = gen_alloc.allocate(2)?;
+        let stale_key = gen_alloc.allocate(3)?;
+        gen_alloc.deallocate(&stale_key)?;
+
+        let results = gen_alloc.bulk_set(vec![(key1, 10), (stale_key, 30), (key2, 20)]);
+        assert_eq!(results.len(), 3);
+        assert_eq!(*results[0].as_ref().expect("Valid key"), 1);
+        assert!(results[1].is_err());
+        assert_eq!(*results[2].as_ref().expect("Valid key"), 2);
+
+        assert_eq!(gen_alloc.get(&key1), Some(&10));
+        assert_eq!(gen_alloc.get(&key2), Some(&20));
+        assert_eq!(
+            gen_alloc.get(&stale_key),
+            None,
+            "Stale key must not be filled"
+        );
+        assert_eq!(gen_alloc.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_new_with_first_free() -> Result<(), Error> {
+        let num_free = 3;
+        let mut gen_alloc = GenIndexAllocator::new_with_first_free(num_free);
+        assert_eq!(gen_alloc.slots.len(), num_free);
+        assert_eq!(gen_alloc.free_indices.len(), num_free);
+        assert!(gen_alloc.is_empty());
+
+        let keys: Vec<_> = (0..num_free + 1)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        for (value, key) in keys.iter().enumerate() {
+            assert_eq!(
+                key.index, value,
+                "Pre-created slots should be used in order"
+            );
+            assert_eq!(gen_alloc.get(key), Some(&value));
+        }
+        assert_eq!(gen_alloc.slots.len(), num_free + 1);
+        assert!(gen_alloc.free_indices.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_current_key_at() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let stale_key = gen_alloc.allocate(1)?;
+        gen_alloc.deallocate(&stale_key)?;
+        assert_eq!(gen_alloc.current_key_at(stale_key.index), None);
+
+        let new_key = gen_alloc.allocate(2)?;
+        assert_eq!(new_key.index, stale_key.index);
+        assert_eq!(gen_alloc.current_key_at(stale_key.index), Some(new_key));
+        assert_ne!(gen_alloc.current_key_at(stale_key.index), Some(stale_key));
+
+        assert_eq!(gen_alloc.current_key_at(5), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capacity_growth_hook() -> Result<(), Error> {
+        use std::sync::{Arc, Mutex};
+
+        let capacity = 4;
+        let mut gen_alloc = GenIndexAllocator::with_capacity(capacity);
+        let growths = Arc::new(Mutex::new(Vec::new()));
+        let hook_growths = Arc::clone(&growths);
+        gen_alloc.set_capacity_growth_hook(move |old_cap, new_cap| {
+            hook_growths.lock().unwrap().push((old_cap, new_cap))
+        });
+
+        for value in 0..capacity {
+            gen_alloc.allocate(value)?;
+        }
+        assert!(
+            growths.lock().unwrap().is_empty(),
+            "No growth within capacity"
+        );
+
+        gen_alloc.allocate(capacity)?;
+        assert_eq!(growths.lock().unwrap().len(), 1);
+        let (old_cap, new_cap) = growths.lock().unwrap()[0];
+        assert_eq!(old_cap, capacity);
+        assert!(new_cap > old_cap);
+        assert_eq!(new_cap, gen_alloc.slots.capacity());
+
+        gen_alloc.remove_capacity_growth_hook();
+        for value in 0..new_cap {
+            gen_alloc.allocate(value)?;
+        }
+        assert_eq!(
+            growths.lock().unwrap().len(),
+            1,
+            "Removed hook should not fire"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_packed() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..5)
+            .map(|value| gen_alloc.allocate(value * 10).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[2])?;
+
+        let (packed_keys, packed_values) = gen_alloc.to_packed();
+        assert_eq!(packed_keys.len(), packed_values.len());
+        assert_eq!(packed_keys.len(), gen_alloc.len());
+        assert_eq!(packed_values, vec![0, 10, 30, 40]);
+        for (key, value) in packed_keys.iter().zip(packed_values.iter()) {
+            assert_eq!(gen_alloc.get(key), Some(value));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_packed_round_trip() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..5)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+        gen_alloc.deallocate(&keys[3])?;
+        let reused_key = gen_alloc.allocate(11)?;
+        assert_eq!(reused_key.generation, 1);
+
+        let (packed_keys, packed_values) = gen_alloc.to_packed();
+        let mut restored = GenIndexAllocator::from_packed(&packed_keys, packed_values)?;
+        assert_eq!(restored.count(), gen_alloc.len());
+        assert_eq!(restored.slots.len(), gen_alloc.slots.len());
+        for (key, value) in gen_alloc.iter() {
+            assert_eq!(restored.get(&key), Some(value));
+        }
+
+        // Vacant slots should be on the free list again
+        assert_eq!(restored.free_indices.len(), 1);
+        let new_key = restored.allocate(33)?;
+        assert_eq!(restored.get(&new_key), Some(&33));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_packed_rejects_invalid_input() {
+        let key = GenIndex {
+            index: 0,
+            generation: 0,
+        };
+        assert!(GenIndexAllocator::from_packed(&[key], vec![1, 2]).is_err());
+        assert!(GenIndexAllocator::from_packed(&[key, key], vec![1, 2]).is_err());
+
+        // The number of slots for this index would overflow
+        let key = GenIndex {
+            index: usize::MAX,
+            generation: 0,
+        };
+        assert!(GenIndexAllocator::from_packed(&[key], vec![1]).is_err());
+
+        // Slots up to this index cannot be reserved
+        let key = GenIndex {
+            index: usize::MAX - 1,
+            generation: 0,
+        };
+        assert!(GenIndexAllocator::from_packed(&[key], vec![1]).is_err());
+    }
+
+    #[test]
+    fn test_with_prefilled() {
+        let count = 6;
+        let (gen_alloc, keys) = GenIndexAllocator::with_prefilled(count, String::from("tile"));
+        assert_eq!(gen_alloc.len(), count);
+        assert_eq!(gen_alloc.count(), count);
+        assert_eq!(keys.len(), count);
+        for key in keys.iter() {
+            assert_eq!(gen_alloc.get(key).map(String::as_str), Some("tile"));
+        }
+    }
+
+    #[test]
+    fn test_ensure_index() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::<i32>::with_capacity(10);
+        let key = gen_alloc.ensure_index(5)?;
+        assert_eq!(key.index, 5);
+        assert_eq!(key.generation, 0);
+        assert_eq!(gen_alloc.slots.len(), 6);
+        assert_eq!(gen_alloc.free_indices.len(), 6);
+        assert!(gen_alloc.is_empty());
+        assert_eq!(gen_alloc.get(&key), None);
+
+        // Existing slots are not padded again and report their current key
+        let live_key = gen_alloc.allocate(1)?;
+        assert_eq!(
+            live_key.index, 0,
+            "Padding slots are handed out from low indices"
+        );
+        assert_eq!(gen_alloc.ensure_index(0)?, live_key);
+        assert_eq!(gen_alloc.slots.len(), 6);
+
+        // A vacant slot reports the key its next value will get
+        gen_alloc.deallocate(&live_key)?;
+        let next_key = gen_alloc.ensure_index(0)?;
+        assert_eq!(next_key.generation, 1);
+        assert_eq!(gen_alloc.allocate(2)?, next_key);
+
+        // A retired slot has no next key
+        gen_alloc.force_generation(next_key.index, u32::MAX);
+        let retired = gen_alloc.current_key_at(next_key.index).unwrap();
+        gen_alloc.deallocate(&retired)?;
+        assert!(gen_alloc.ensure_index(retired.index).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_trim_free_list_duplicates() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..4)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+        gen_alloc.deallocate(&keys[2])?;
+        assert_eq!(gen_alloc.trim_free_list_duplicates(), 0);
+
+        // Simulate a free list corrupted by double frees, an occupied and a missing index
+        gen_alloc.free_indices = vec![1, 2, 1, 0, 2, 99];
+        assert_eq!(gen_alloc.trim_free_list_duplicates(), 4);
+        assert_eq!(gen_alloc.free_indices, vec![1, 2]);
+
+        // Reallocating should not hand out an occupied slot anymore
+        gen_alloc.allocate(10)?;
+        gen_alloc.allocate(11)?;
+        assert!(gen_alloc.free_indices.is_empty());
+        assert_eq!(gen_alloc.get(&keys[0]), Some(&0));
+        assert_eq!(gen_alloc.count(), 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_keys_sorted() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[4])?;
+        gen_alloc.deallocate(&keys[1])?;
+        // Reuse the freed slots in non-ascending order
+        gen_alloc.allocate(10)?;
+        gen_alloc.allocate(11)?;
+
+        let sorted_keys: Vec<_> = gen_alloc.iter_keys_sorted().collect();
+        assert_eq!(sorted_keys.len(), 6);
+        assert!(sorted_keys
+            .windows(2)
+            .all(|pair| pair[0].index < pair[1].index));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_fold_values() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (1..=5)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[2])?;
+
+        let sum = gen_alloc.fold_values(0, |acc, _, value| acc + value);
+        assert_eq!(sum, 1 + 2 + 4 + 5);
+
+        let index_sum = gen_alloc.fold_values(0, |acc, key, _| acc + key.index);
+        assert_eq!(index_sum, 1 + 3 + 4);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_partition() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[5])?;
+
+        let (even, odd, remap) = gen_alloc.partition(|_, value| value % 2 == 0);
+        assert_eq!(even.len(), 3);
+        assert_eq!(odd.len(), 2);
+        assert_eq!(remap.len(), 5);
+        assert!(!remap.contains_key(&keys[5]));
+
+        for (value, key) in keys.iter().enumerate().take(5) {
+            let (is_even, new_key) = remap[key];
+            assert_eq!(is_even, value % 2 == 0);
+            let target = if is_even { &even } else { &odd };
+            assert_eq!(target.get(&new_key), Some(&value));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_value_at() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let key1 = gen_alloc.allocate(1)?;
+        let key2 = gen_alloc.allocate(2)?;
+        gen_alloc.deallocate(&key1)?;
+
+        assert_eq!(gen_alloc.value_at(key2.index), Some(&2));
+        assert_eq!(gen_alloc.value_at(key1.index), None);
+        assert_eq!(gen_alloc.value_at(2), None);
+        assert_eq!(gen_alloc.value_at(usize::MAX), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_update_all_and_map_in_place() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..4)
+            .map(|value| gen_alloc.allocate(vec![value]).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+
+        gen_alloc.update_all(|key, value| value.push(key.index * 10));
+        assert_eq!(gen_alloc.get(&keys[0]), Some(&vec![0, 0]));
+        assert_eq!(gen_alloc.get(&keys[3]), Some(&vec![3, 30]));
+
+        gen_alloc.map_in_place(|_, value| vec![value.iter().sum()]);
+        assert_eq!(gen_alloc.get(&keys[0]), Some(&vec![0]));
+        assert_eq!(gen_alloc.get(&keys[2]), Some(&vec![22]));
+        assert_eq!(gen_alloc.get(&keys[3]), Some(&vec![33]));
+        assert_eq!(gen_alloc.count(), 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_debug_dump() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let key1 = gen_alloc.allocate("first")?;
+        gen_alloc.allocate("second")?;
+        gen_alloc.deallocate(&key1)?;
+
+        let mut dump = String::new();
+        gen_alloc.debug_dump(&mut dump)?;
+        let lines: Vec<_> = dump.lines().collect();
+        assert_eq!(
+            lines,
+            vec![
+                "slot 0: generation 1, vacant",
+                "slot 1: generation 0, occupied: \"second\"",
+                "free list: [0]",
+            ]
+        );
+
+        Ok(())
+    }
+
+    fn alloc_at_generation_ceiling(policy: OverflowPolicy) -> (GenIndexAllocator<i32>, GenIndex) {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        gen_alloc.set_overflow_policy(policy);
+        let key = gen_alloc.allocate(1).expect("Should allocate");
+        gen_alloc.force_generation(key.index, u32::MAX);
+        let key = gen_alloc
+            .current_key_at(key.index)
+            .expect("Slot is occupied");
+        (gen_alloc, key)
+    }
+
+    #[test]
+    fn test_overflow_policy_retire() -> Result<(), Error> {
+        let (mut gen_alloc, key) = alloc_at_generation_ceiling(OverflowPolicy::default());
+        assert_eq!(gen_alloc.overflow_policy(), OverflowPolicy::Retire);
+
+        assert_eq!(gen_alloc.deallocate(&key)?, Some(1));
+        assert!(
+            gen_alloc.free_indices.is_empty(),
+            "Retired slot is not reused"
+        );
+        assert_eq!(gen_alloc.count(), 0);
+
+        let new_key = gen_alloc.allocate(2)?;
+        assert_ne!(new_key.index, key.index);
+        assert_eq!(gen_alloc.get(&key), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow_policy_wrap() -> Result<(), Error> {
+        let (mut gen_alloc, key) = alloc_at_generation_ceiling(OverflowPolicy::Wrap);
+
+        assert_eq!(gen_alloc.deallocate(&key)?, Some(1));
+        let new_key = gen_alloc.allocate(2)?;
+        assert_eq!(new_key.index, key.index);
+        assert_eq!(new_key.generation, 0);
+        assert_eq!(gen_alloc.get(&key), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_overflow_policy_error() -> Result<(), Error> {
+        let (mut gen_alloc, key) = alloc_at_generation_ceiling(OverflowPolicy::Error);
+
+        assert!(gen_alloc.deallocate(&key).is_err());
+        assert_eq!(gen_alloc.get(&key), Some(&1), "Value should be kept");
+        assert_eq!(gen_alloc.count(), 1);
+
+        // Methods that cannot fail retire the slot instead
+        gen_alloc.clear();
+        assert!(gen_alloc.free_indices.is_empty());
+        assert_eq!(gen_alloc.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    #[should_panic(expected = "Generation overflow")]
+    fn test_overflow_policy_panic() {
+        let (mut gen_alloc, key) = alloc_at_generation_ceiling(OverflowPolicy::Panic);
+        let _ = gen_alloc.deallocate(&key);
+    }
+
+    #[test]
+    fn test_get2() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let key1 = gen_alloc.allocate(1)?;
+        let key2 = gen_alloc.allocate(2)?;
+        let stale_key = gen_alloc.allocate(3)?;
+        gen_alloc.deallocate(&stale_key)?;
+
+        assert_eq!(gen_alloc.get2(&key1, &key2), (Some(&1), Some(&2)));
+        assert_eq!(gen_alloc.get2(&key1, &stale_key), (Some(&1), None));
+        assert_eq!(gen_alloc.get2(&stale_key, &key2), (None, Some(&2)));
+        assert_eq!(gen_alloc.get2(&key2, &key2), (Some(&2), Some(&2)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recycle_into() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let key1 = gen_alloc.allocate(vec![1; 8])?;
+        let key2 = gen_alloc.allocate(vec![2; 8])?;
+        let mut pool = Vec::new();
+
+        gen_alloc.recycle_into(&key1, &mut pool)?;
+        assert_eq!(pool, vec![vec![1; 8]]);
+        assert!(!gen_alloc.contains(&key1));
+        assert_eq!(gen_alloc.count(), 1);
+        assert_eq!(gen_alloc.free_indices, vec![key1.index]);
+
+        // A stale key fails without touching the pool
+        assert!(gen_alloc.recycle_into(&key1, &mut pool).is_err());
+        assert_eq!(pool.len(), 1);
+
+        gen_alloc.recycle_into(&key2, &mut pool)?;
+        assert_eq!(pool.len(), 2);
+        assert!(gen_alloc.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_sorted_keys_by() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let values = ["pear", "fig", "apple", "kiwi", "banana", "plum"];
+        let keys: Vec<_> = values
+            .iter()
+            .map(|value| gen_alloc.allocate(*value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[4])?;
+
+        let sorted_keys = gen_alloc.sorted_keys_by(|value| value.len());
+        let sorted_values: Vec<_> = sorted_keys
+            .iter()
+            .map(|key| *gen_alloc.get(key).expect("Key should be valid"))
+            .collect();
+
+        let mut reference: Vec<_> = values
+            .iter()
+            .filter(|value| **value != "banana")
+            .copied()
+            .collect();
+        reference.sort_by_key(|value| value.len());
+        assert_eq!(sorted_values, reference);
+        assert_eq!(sorted_values, vec!["fig", "pear", "kiwi", "plum", "apple"]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_disjoint_pairs_mut() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+
+        let pairs = [(keys[0], keys[5]), (keys[2], keys[1]), (keys[3], keys[4])];
+        for (a, b) in gen_alloc.get_disjoint_pairs_mut(&pairs)? {
+            std::mem::swap(a, b);
+        }
+        let values: Vec<_> = gen_alloc.iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, vec![5, 2, 1, 4, 3, 0]);
+
+        // The same index in two different pairs is rejected
+        let pairs = [(keys[0], keys[1]), (keys[2], keys[1])];
+        assert_eq!(
+            gen_alloc.get_disjoint_pairs_mut(&pairs).err(),
+            Some(GetManyError::DuplicateIndex(1))
+        );
+
+        // The repeat coming first in the batch is reported, not the lowest repeated index
+        let pairs = [(keys[2], keys[4]), (keys[4], keys[2])];
+        assert_eq!(
+            gen_alloc.get_disjoint_pairs_mut(&pairs).err(),
+            Some(GetManyError::DuplicateIndex(4))
+        );
+
+        // The same index within one pair is rejected
+        let pairs = [(keys[3], keys[3])];
+        assert_eq!(
+            gen_alloc.get_disjoint_pairs_mut(&pairs).err(),
+            Some(GetManyError::DuplicateIndex(3))
+        );
+
+        gen_alloc.deallocate(&keys[4])?;
+        let pairs = [(keys[0], keys[1]), (keys[2], keys[4])];
+        assert_eq!(
+            gen_alloc.get_disjoint_pairs_mut(&pairs).err(),
+            Some(GetManyError::InvalidKey(keys[4]))
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_collect_with_keys() {
+        let (gen_alloc, keys) =
+            GenIndexAllocator::collect_with_keys(["a", "b", "c"].iter().map(|s| s.to_string()));
+        assert_eq!(gen_alloc.len(), 3);
+        assert_eq!(keys.len(), 3);
+        for (key, expected) in keys.iter().zip(["a", "b", "c"]) {
+            assert_eq!(gen_alloc.get(key).map(String::as_str), Some(expected));
+        }
+    }
+
+    #[test]
+    fn test_compact() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[0])?;
+        gen_alloc.deallocate(&keys[2])?;
+        gen_alloc.deallocate(&keys[5])?;
+
+        let remap = gen_alloc.compact();
+        assert_eq!(remap.len(), 3);
+        assert_eq!(
+            gen_alloc.slots.len(),
+            3,
+            "Trailing free slots should be dropped"
+        );
+        assert!(gen_alloc.free_indices.is_empty());
+        assert_eq!(gen_alloc.count(), 3);
+
+        for (value, old_key) in keys.iter().enumerate() {
+            match remap.get(old_key) {
+                None => assert!([0, 2, 5].contains(&value)),
+                Some(new_key) => {
+                    assert!(new_key.index < 3);
+                    assert_eq!(gen_alloc.get(new_key), Some(&value));
+                }
+            }
+        }
+        assert_eq!(remap[&keys[1]], keys[1], "Entries in place keep their key");
+        assert_eq!(gen_alloc.get(&keys[4]), None, "Moved entries get a new key");
+
+        // New slots at truncated indices must not revive stale keys
+        let new_keys: Vec<_> = (0..3)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        for key in [keys[3], keys[4], keys[5]] {
+            assert_eq!(gen_alloc.get(&key), None);
+        }
+        assert!(new_keys.iter().all(|key| gen_alloc.contains(key)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_auto_compact() -> Result<(), Error> {
+        use std::sync::{Arc, Mutex};
+
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..4)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+
+        let remaps = Arc::new(Mutex::new(Vec::new()));
+        let hook_remaps = Arc::clone(&remaps);
+        gen_alloc.enable_auto_compact(0.4, move |remap| hook_remaps.lock().unwrap().push(remap));
+
+        // 1 of 4 slots free is below the threshold
+        gen_alloc.deallocate(&keys[0])?;
+        assert!(remaps.lock().unwrap().is_empty());
+        assert_eq!(gen_alloc.slots.len(), 4);
+
+        // 2 of 4 slots free crosses the threshold
+        gen_alloc.deallocate(&keys[1])?;
+        assert_eq!(remaps.lock().unwrap().len(), 1);
+        assert_eq!(gen_alloc.slots.len(), 2);
+        assert!(gen_alloc.free_indices.is_empty());
+
+        let remap = remaps.lock().unwrap()[0].clone();
+        assert_eq!(remap.len(), 2);
+        for (value, old_key) in keys.iter().enumerate().skip(2) {
+            assert_eq!(gen_alloc.get(&remap[old_key]), Some(&value));
+        }
+
+        gen_alloc.disable_auto_compact();
+        gen_alloc.deallocate(&remap[&keys[2]])?;
+        assert_eq!(remaps.lock().unwrap().len(), 1);
+        assert_eq!(gen_alloc.slots.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_from_options() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::from_options(vec![Some(1), None, Some(3), None]);
+        assert_eq!(gen_alloc.count(), 2);
+        assert_eq!(gen_alloc.value_at(2), Some(&3));
+        assert_eq!(gen_alloc.allocate(10)?.index, 1);
+        assert_eq!(gen_alloc.allocate(11)?.index, 3);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_from_options() -> Result<(), Error> {
+        let values = vec![Some(1), None, Some(3), None];
+        let mut gen_alloc = GenIndexAllocator::try_from_options(values.clone(), vec![1, 3])?;
+        assert_eq!(gen_alloc.count(), 2);
+        assert_eq!(gen_alloc.allocate(10)?.index, 3, "Free list order is kept");
+
+        // Free index pointing at a value
+        assert!(GenIndexAllocator::try_from_options(values.clone(), vec![1, 2, 3]).is_err());
+        // Duplicate free index
+        assert!(GenIndexAllocator::try_from_options(values.clone(), vec![1, 3, 1]).is_err());
+        // Out of range free index
+        assert!(GenIndexAllocator::try_from_options(values.clone(), vec![1, 3, 4]).is_err());
+        // Vacant slot missing from the free list
+        assert!(GenIndexAllocator::try_from_options(values, vec![1]).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_live_generation_for() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let old_key = gen_alloc.allocate(0)?;
+        assert_eq!(gen_alloc.live_generation_for(&old_key), Some(0));
+
+        let mut key = old_key;
+        for value in 1..=3 {
+            gen_alloc.deallocate(&key)?;
+            key = gen_alloc.allocate(value)?;
+            assert_eq!(key.index, old_key.index);
+        }
+
+        let live = gen_alloc
+            .live_generation_for(&old_key)
+            .expect("Index is in range");
+        assert_eq!(live - old_key.generation(), 3);
+        assert_eq!(gen_alloc.live_generation_for(&key), Some(key.generation()));
+
+        let out_of_range = GenIndex {
+            index: 10,
+            generation: 0,
+        };
+        assert_eq!(gen_alloc.live_generation_for(&out_of_range), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear_range() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[2])?;
+
+        gen_alloc.clear_range(1..4);
+        assert_eq!(gen_alloc.count(), 3);
+        for (value, key) in keys.iter().enumerate() {
+            assert_eq!(gen_alloc.contains(key), !(1..4).contains(&value));
+        }
+        assert_eq!(gen_alloc.free_indices.len(), 3);
+        assert_eq!(gen_alloc.live_generation_for(&keys[1]), Some(1));
+        assert_eq!(gen_alloc.live_generation_for(&keys[2]), Some(1));
+        assert_eq!(gen_alloc.live_generation_for(&keys[4]), Some(0));
+
+        // Ranges beyond the slots are clamped
+        gen_alloc.clear_range(5..100);
+        assert_eq!(gen_alloc.count(), 2);
+        gen_alloc.clear_range(50..100);
+        assert_eq!(gen_alloc.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_shrink_and_remap() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(100);
+        let keys: Vec<_> = (0..10)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        for key in keys.iter().step_by(3) {
+            gen_alloc.deallocate(key)?;
+        }
+
+        let remap = gen_alloc.shrink_and_remap();
+        assert_eq!(gen_alloc.slots.len(), 6);
+        assert!(gen_alloc.slots.iter().all(|slot| slot.occupied));
+        assert_eq!(gen_alloc.slots.capacity(), gen_alloc.slots.len());
+        assert!(gen_alloc.free_indices.is_empty());
+
+        assert_eq!(remap.len(), 6);
+        for (value, old_key) in keys.iter().enumerate() {
+            if value % 3 != 0 {
+                assert_eq!(gen_alloc.get(&remap[old_key]), Some(&value));
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_entries_raw() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let key1 = gen_alloc.allocate(1)?;
+        gen_alloc.allocate(2)?;
+        gen_alloc.allocate(3)?;
+        gen_alloc.deallocate(&key1)?;
+
+        let raw: Vec<_> = gen_alloc.iter_entries_raw().collect();
+        assert_eq!(raw, vec![(0, 1, None), (1, 0, Some(&2)), (2, 0, Some(&3))]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_put() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+
+        // Replace an occupied value
+        let key = gen_alloc.allocate(1)?;
+        assert_eq!(gen_alloc.put(&key, 2)?, Some(1));
+        assert_eq!(gen_alloc.get(&key), Some(&2));
+        assert_eq!(gen_alloc.count(), 1);
+
+        // Insert into a vacant slot with a valid key
+        let vacant_key = gen_alloc.ensure_index(3)?;
+        assert_eq!(gen_alloc.put(&vacant_key, 3)?, None);
+        assert_eq!(gen_alloc.get(&vacant_key), Some(&3));
+        assert_eq!(gen_alloc.count(), 2);
+        assert!(!gen_alloc.free_indices.contains(&vacant_key.index));
+
+        // Stale and out-of-range keys are rejected
+        gen_alloc.deallocate(&key)?;
+        assert!(gen_alloc.put(&key, 4).is_err());
+        assert_eq!(gen_alloc.get(&key), None);
+        let out_of_range = GenIndex {
+            index: 10,
+            generation: 0,
+        };
+        assert!(gen_alloc.put(&out_of_range, 5).is_err());
+        assert_eq!(gen_alloc.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_put_rejects_retired_slot() -> Result<(), Error> {
+        let (mut gen_alloc, key) = alloc_at_generation_ceiling(OverflowPolicy::Retire);
+        gen_alloc.deallocate(&key)?;
+        let err = gen_alloc.put(&key, 2).expect_err("Slot is retired");
+        assert_eq!(err.to_string(), "GenIndexAllocator::put: Slot is retired");
+        assert_eq!(gen_alloc.count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capacity_report() -> Result<(), Error> {
+        let gen_alloc = GenIndexAllocator::<i32>::with_capacity(8);
+        let report = gen_alloc.capacity_report();
+        assert_eq!(report.slots, 0);
+        assert_eq!(report.fragmentation, 0.0);
+
+        let mut gen_alloc = GenIndexAllocator::with_capacity(8);
+        let keys: Vec<_> = (0..4)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+
+        let report = gen_alloc.capacity_report();
+        assert_eq!(
+            report,
+            CapacityReport {
+                slots: gen_alloc.slots.len(),
+                occupied: gen_alloc.len(),
+                free: gen_alloc.free_indices.len(),
+                entries_capacity: gen_alloc.slots.capacity(),
+                free_capacity: gen_alloc.free_indices.capacity(),
+                fragmentation: 0.25,
+            }
+        );
+        assert_eq!(report.slots, 4);
+        assert_eq!(report.occupied, 3);
+        assert_eq!(report.free, 1);
+        assert_eq!(report.entries_capacity, 8);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_retain_with() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+
+        let mut removed = Vec::new();
+        gen_alloc.retain_with(
+            |_, value| *value < 4,
+            |key, value| removed.push((key, value)),
+        );
+        assert_eq!(removed, vec![(keys[4], 4), (keys[5], 5)]);
+        assert_eq!(gen_alloc.count(), 4);
+        assert!(!gen_alloc.contains(&keys[4]));
+        assert_eq!(gen_alloc.free_indices.len(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_zero_sized_values() -> Result<(), Error> {
+        // Unit values add at most a tag byte per slot on top of the metadata
+        let slot_size = std::mem::size_of::<Slot>() + std::mem::size_of::<Option<()>>();
+        assert!(slot_size <= std::mem::size_of::<Slot>() + 1);
+
+        let num_values = 1000;
+        let mut gen_alloc = GenIndexAllocator::with_capacity(num_values);
+        let keys: Vec<_> = (0..num_values)
+            .map(|_| gen_alloc.allocate(()).expect("Should allocate"))
+            .collect();
+        assert_eq!(gen_alloc.count(), num_values);
+        assert!(keys.iter().all(|key| gen_alloc.get(key) == Some(&())));
+
+        for key in keys.iter().step_by(2) {
+            assert_eq!(gen_alloc.deallocate(key)?, Some(()));
+        }
+        assert_eq!(gen_alloc.count(), num_values / 2);
+        for (i, key) in keys.iter().enumerate() {
+            assert_eq!(gen_alloc.contains(key), i % 2 == 1);
+        }
+
+        let reused_keys: Vec<_> = (0..num_values / 2)
+            .map(|_| gen_alloc.allocate(()).expect("Should allocate"))
+            .collect();
+        assert_eq!(gen_alloc.count(), num_values);
+        assert_eq!(gen_alloc.slots.len(), num_values, "Freed slots are reused");
+        assert!(reused_keys.iter().all(|key| key.generation == 1));
+        assert!(keys.iter().step_by(2).all(|key| !gen_alloc.contains(key)));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_looking_values_are_occupied() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let none_key = gen_alloc.allocate(None::<i32>)?;
+        let some_key = gen_alloc.allocate(Some(1))?;
+
+        assert!(gen_alloc.contains(&none_key));
+        assert_eq!(gen_alloc.get(&none_key), Some(&None));
+        assert_eq!(gen_alloc.count(), 2);
+        assert_eq!(gen_alloc.set(&some_key, None)?, Some(1));
+        assert!(gen_alloc.contains(&some_key));
+
+        assert_eq!(gen_alloc.deallocate(&none_key)?, Some(None));
+        assert!(!gen_alloc.contains(&none_key));
+        assert_eq!(gen_alloc.count(), 1);
+
+        let mut unit_alloc = GenIndexAllocator::with_capacity(10);
+        let unit_key = unit_alloc.allocate(())?;
+        assert!(unit_alloc.contains(&unit_key));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_occupied_flag_follows_value() -> Result<(), Error> {
+        let flags_in_sync = |gen_alloc: &GenIndexAllocator<Option<i32>>| {
+            gen_alloc.slots.len() == gen_alloc.values.len()
+                && gen_alloc
+                    .slots
+                    .iter()
+                    .zip(gen_alloc.values.iter())
+                    .all(|(slot, value)| slot.occupied == value.is_some())
+                && gen_alloc.slots.iter().filter(|slot| slot.occupied).count() == gen_alloc.len()
+        };
+
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(Some(value)).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+        gen_alloc.swap_remove_index(keys[2].index());
+        let vacant_key = gen_alloc.ensure_index(8)?;
+        gen_alloc.put(&vacant_key, None)?;
+        assert!(flags_in_sync(&gen_alloc));
+
+        gen_alloc.compact();
+        assert!(flags_in_sync(&gen_alloc));
+        gen_alloc.retain(|_, value| value.is_none());
+        assert!(flags_in_sync(&gen_alloc));
+        assert_eq!(gen_alloc.len(), 1);
+
+        #[cfg(feature = "serde")]
+        {
+            let json = serde_json::to_string(&gen_alloc)?;
+            let loaded: GenIndexAllocator<Option<i32>> = serde_json::from_str(&json)?;
+            assert!(flags_in_sync(&loaded));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_begin_reserve() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        gen_alloc.allocate(0)?;
+
+        // A committed reservation yields a usable key
+        let reservation = gen_alloc.begin_reserve()?;
+        let reserved_key = reservation.key();
+        let key = reservation.commit(1);
+        assert_eq!(key, reserved_key);
+        assert_eq!(gen_alloc.get(&key), Some(&1));
+        assert_eq!(gen_alloc.count(), 2);
+
+        // A dropped reservation frees the slot and invalidates its key
+        let reserved_key = {
+            let reservation = gen_alloc.begin_reserve()?;
+            reservation.key()
+        };
+        assert_eq!(gen_alloc.count(), 2);
+        assert_eq!(gen_alloc.free_indices, vec![reserved_key.index]);
+        assert!(gen_alloc.put(&reserved_key, 2).is_err());
+
+        let new_key = gen_alloc.allocate(3)?;
+        assert_eq!(new_key.index, reserved_key.index);
+        assert_eq!(new_key.generation, reserved_key.generation + 1);
+        assert_eq!(gen_alloc.get(&reserved_key), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_mut_or_insert_default() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::<u32>::with_capacity(10);
+
+        let key = gen_alloc.allocate(5)?;
+        *gen_alloc.get_mut_or_insert_default(&key)? += 1;
+        assert_eq!(gen_alloc.get(&key), Some(&6));
+        assert_eq!(gen_alloc.count(), 1);
+
+        let vacant_key = gen_alloc.ensure_index(4)?;
+        *gen_alloc.get_mut_or_insert_default(&vacant_key)? += 1;
+        *gen_alloc.get_mut_or_insert_default(&vacant_key)? += 1;
+        assert_eq!(gen_alloc.get(&vacant_key), Some(&2));
+        assert_eq!(gen_alloc.count(), 2);
+        assert!(!gen_alloc.free_indices.contains(&vacant_key.index));
+
+        gen_alloc.deallocate(&key)?;
+        assert!(gen_alloc.get_mut_or_insert_default(&key).is_err());
+        assert_eq!(gen_alloc.count(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compact_and_renumber() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let mut keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        // Age some slots so that their generations are non-zero
+        for _ in 0..3 {
+            gen_alloc.deallocate(&keys[3])?;
+            keys[3] = gen_alloc.allocate(3)?;
+        }
+        gen_alloc.deallocate(&keys[0])?;
+        gen_alloc.deallocate(&keys[4])?;
+
+        let remap = gen_alloc.compact_and_renumber();
+        assert_eq!(gen_alloc.slots.len(), 4);
+        assert!(gen_alloc.slots.iter().all(|slot| slot.occupied));
+        assert!(gen_alloc.free_indices.is_empty());
+        assert!(gen_alloc.keys().all(|key| key.generation == 0));
+        assert_eq!(gen_alloc.count(), 4);
+
+        assert_eq!(remap.len(), 4);
+        for value in [1, 2, 3, 5] {
+            let new_key = remap[&keys[value]];
+            assert_eq!(new_key.generation, 0);
+            assert_eq!(gen_alloc.get(&new_key), Some(&value));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_iter_mut_where() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[2])?;
+
+        let mut visited = Vec::new();
+        for (key, value) in gen_alloc.iter_mut_where(|_, value| value % 2 == 0) {
+            visited.push(key);
+            *value *= 10;
+        }
+        assert_eq!(visited, vec![keys[0], keys[4]]);
+
+        let values: Vec<_> = gen_alloc.iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, vec![0, 1, 3, 40, 5]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_index() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let index1 = gen_alloc.allocate_index("first")?;
+        let index2 = gen_alloc.allocate_index("second")?;
+        assert_eq!(gen_alloc.value_at(index1), Some(&"first"));
+        assert_eq!(gen_alloc.value_at(index2), Some(&"second"));
+
+        let generation = gen_alloc.generation_at(index2).expect("Index is in range");
+        let key = GenIndex::new(index2, generation);
+        assert_eq!(gen_alloc.get(&key), Some(&"second"));
+        assert_eq!(gen_alloc.generation_at(5), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_vec_of_options_round_trip() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..4)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+        let reused_key = gen_alloc.allocate(10)?;
+        gen_alloc.deallocate(&keys[3])?;
+        assert_eq!(reused_key.generation, 1);
+
+        let values: Vec<Option<i32>> = gen_alloc.into();
+        assert_eq!(values, vec![Some(0), Some(10), Some(2), None]);
+
+        let restored = GenIndexAllocator::from(values.clone());
+        assert_eq!(restored.count(), 3);
+        assert_eq!(
+            restored.get(&reused_key),
+            None,
+            "Generations are not preserved"
+        );
+        assert_eq!(restored.value_at(reused_key.index), Some(&10));
+
+        let round_trip: Vec<Option<i32>> = restored.into();
+        assert_eq!(round_trip, values);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_contains_all_and_any() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..3)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        assert!(gen_alloc.contains_all(&keys));
+        assert!(gen_alloc.contains_any(&keys));
+
+        gen_alloc.deallocate(&keys[1])?;
+        assert!(!gen_alloc.contains_all(&keys));
+        assert!(gen_alloc.contains_any(&keys));
+        assert!(!gen_alloc.contains_any(&keys[1..2]));
+
+        assert!(gen_alloc.contains_all(&[]));
+        assert!(!gen_alloc.contains_any(&[]));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_force_generation_at_ceiling() -> Result<(), Error> {
+        for policy in [
+            OverflowPolicy::Retire,
+            OverflowPolicy::Wrap,
+            OverflowPolicy::Error,
+        ] {
+            let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+            gen_alloc.set_overflow_policy(policy);
+            let old_key = gen_alloc.allocate(1)?;
+
+            // Push the slot to one below the ceiling and let a regular free reach it
+            gen_alloc.force_generation(old_key.index, u32::MAX - 1);
+            assert!(
+                !gen_alloc.contains(&old_key),
+                "Forced generation invalidates the key"
+            );
+            let key = GenIndex::new(old_key.index, u32::MAX - 1);
+            gen_alloc.deallocate(&key)?;
+            let key = gen_alloc.allocate(2)?;
+            assert_eq!(key.generation, u32::MAX);
+
+            let result = gen_alloc.deallocate(&key);
+            match policy {
+                OverflowPolicy::Retire => {
+                    assert_eq!(result?, Some(2));
+                    assert!(gen_alloc.free_indices.is_empty());
+                }
+                OverflowPolicy::Wrap => {
+                    assert_eq!(result?, Some(2));
+                    assert_eq!(gen_alloc.generation_at(key.index), Some(0));
+                    assert_eq!(gen_alloc.free_indices, vec![key.index]);
+                }
+                OverflowPolicy::Error => {
+                    assert!(result.is_err());
+                    assert_eq!(gen_alloc.get(&key), Some(&2));
+                }
+                OverflowPolicy::Panic => unreachable!(),
+            }
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recommended_capacities() -> Result<(), Error> {
+        assert_eq!(
+            GenIndexAllocator::<i32>::recommended_capacities(0, 0),
+            (0, 0)
+        );
+        assert_eq!(
+            GenIndexAllocator::<i32>::recommended_capacities(1000, 100),
+            (1000, 100)
+        );
+        assert_eq!(
+            GenIndexAllocator::<i32>::recommended_capacities(100, 1000),
+            (100, 100),
+            "The free list cannot exceed the number of slots"
+        );
+
+        let (entries_capacity, free_capacity) =
+            GenIndexAllocator::<i32>::recommended_capacities(64, 16);
+        let gen_alloc = GenIndexAllocator::<i32>::with_capacities(entries_capacity, free_capacity);
+        let report = gen_alloc.capacity_report();
+        assert_eq!(report.entries_capacity, 64);
+        assert_eq!(report.free_capacity, 16);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_contents() -> Result<(), Error> {
+        let mut front = GenIndexAllocator::with_capacity(10);
+        let mut back = GenIndexAllocator::with_capacity(10);
+        front.set_overflow_policy(OverflowPolicy::Wrap);
+
+        let front_key = front.allocate("front")?;
+        let stale_key = front.allocate("stale")?;
+        front.deallocate(&stale_key)?;
+        let back_key = back.allocate("back")?;
+
+        front.swap_contents(&mut back);
+
+        // Keys follow their data into the other allocator
+        assert_eq!(back.get(&front_key), Some(&"front"));
+        assert_eq!(front.get(&back_key), Some(&"back"));
+        assert_eq!(front.count(), 1);
+        assert_eq!(back.count(), 1);
+        assert_eq!(back.free_indices, vec![stale_key.index]);
+        assert!(!back.contains(&stale_key));
+
+        // Configuration stays in place
+        assert_eq!(front.overflow_policy(), OverflowPolicy::Wrap);
+        assert_eq!(back.overflow_policy(), OverflowPolicy::Retire);
+
+        // The index cap moves with the slots it was enforced on
+        let mut capped = GenIndexAllocator::with_index_hint(1);
+        let mut wide = GenIndexAllocator::with_capacity(5);
+        for value in 0..5 {
+            wide.allocate(value)?;
+        }
+        capped.swap_contents(&mut wide);
+        assert!(capped.allocate(5)?.index() > 1);
+        assert!(wide.allocate(0).is_ok());
+        assert!(wide.allocate(1).is_ok());
+        assert!(wide.allocate(2).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_contents_clears_change_log() -> Result<(), Error> {
+        let mut front = GenIndexAllocator::with_capacity(10);
+        let mut back = GenIndexAllocator::with_capacity(10);
+        front.enable_change_log(10);
+        back.enable_change_log(10);
+
+        let mut cursor = ChangeCursor::default();
+        front.allocate("front")?;
+        let stale_key = back.allocate("stale")?;
+        back.deallocate(&stale_key)?;
+        let back_key = back.allocate("back")?;
+        front.swap_contents(&mut back);
+
+        // Changes from before the swap refer to data which is now in the other allocator
+        assert_eq!(front.changes_since(&mut cursor).count(), 0);
+        assert_eq!(back.changes_since(&mut ChangeCursor::default()).count(), 0);
+        #[cfg(feature = "reuse-log")]
+        assert!(front.reuse_log().is_empty() && back.reuse_log().is_empty());
+
+        front.deallocate(&back_key)?;
+        let changes: Vec<_> = front.changes_since(&mut cursor).collect();
+        assert_eq!(changes, vec![(back_key, ChangeKind::Removed)]);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_send_and_sync() {
+        fn assert_send<T: Send>() {}
+        fn assert_sync<T: Sync>() {}
+
+        assert_send::<GenIndexAllocator<i32>>();
+        assert_sync::<GenIndexAllocator<i32>>();
+        assert_send::<ArenaView<'static, i32>>();
+        assert_sync::<ArenaView<'static, i32>>();
+        assert_send::<ReservedSlot<'static, i32>>();
+        assert_sync::<ReservedSlot<'static, i32>>();
+        assert_send::<crate::secondary_map::SecondaryMap<i32>>();
+        assert_sync::<crate::secondary_map::SecondaryMap<i32>>();
+
+        // Moving an allocator with hooks to another thread
+        let mut gen_alloc = GenIndexAllocator::with_capacity(1);
+        gen_alloc.set_capacity_growth_hook(|_, _| {});
+        let handle = std::thread::spawn(move || {
+            gen_alloc.allocate(1).expect("Should allocate");
+            gen_alloc.allocate(2).expect("Should allocate");
+            gen_alloc.len()
+        });
+        assert_eq!(handle.join().expect("Thread should not panic"), 2);
+    }
+
+    #[test]
+    fn test_drain_to_vec() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(8);
+        let keys: Vec<_> = (0..5)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+        let capacity = gen_alloc.slots.capacity();
+
+        let drained = gen_alloc.drain_to_vec();
+        assert_eq!(
+            drained,
+            vec![(keys[0], 0), (keys[2], 2), (keys[3], 3), (keys[4], 4)]
+        );
+        assert!(gen_alloc.is_empty());
+        assert_eq!(gen_alloc.slots.len(), 5, "Slots are kept for reuse");
+        assert_eq!(gen_alloc.slots.capacity(), capacity);
+        assert_eq!(gen_alloc.free_indices.len(), 5);
+        for key in keys.iter() {
+            assert!(!gen_alloc.contains(key));
+        }
+
+        assert!(gen_alloc.drain_to_vec().is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_clear_keep_generations() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let keys: Vec<_> = (0..3)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+
+        // Bumping clear invalidates all old keys
+        let mut bumped = GenIndexAllocator::with_capacity(4);
+        let bumped_keys: Vec<_> = (0..3)
+            .map(|value| bumped.allocate(value).expect("Should allocate"))
+            .collect();
+        bumped.clear();
+        assert!(bumped.put(&bumped_keys[0], 10).is_err());
+        assert!(bumped.is_empty());
+
+        // Keeping generations allows filling the old keys again
+        gen_alloc.clear_keep_generations();
+        assert!(gen_alloc.is_empty());
+        assert_eq!(gen_alloc.free_indices.len(), 3);
+        for key in keys.iter() {
+            assert!(gen_alloc.get(key).is_none());
+            assert_eq!(gen_alloc.generation_at(key.index), Some(key.generation));
+        }
+        assert_eq!(gen_alloc.put(&keys[1], 11)?, None);
+        assert_eq!(gen_alloc.get(&keys[1]), Some(&11));
+        assert_eq!(gen_alloc.len(), 1);
+        assert_eq!(gen_alloc.free_indices.len(), 2);
+
+        // The remaining slots are reused with the old generations
+        let new_key = gen_alloc.allocate(12)?;
+        assert!(keys.contains(&new_key));
+
+        // Keys of reserved slots survive both kinds of clear
+        for keep_generations in [false, true] {
+            let mut reserving = GenIndexAllocator::with_capacity(4);
+            reserving.allocate(0)?;
+            let reserved = reserving.reserve_contiguous(2)?;
+            if keep_generations {
+                reserving.clear_keep_generations();
+            } else {
+                reserving.clear();
+            }
+            assert_eq!(
+                reserving.free_indices.len(),
+                1,
+                "Reserved slots stay reserved"
+            );
+            assert_eq!(reserving.put(&reserved[0], 20)?, None);
+            assert_eq!(reserving.get(&reserved[0]), Some(&20));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_pair_mut_by_index() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let key_a = gen_alloc.allocate(1)?;
+        let key_b = gen_alloc.allocate(2)?;
+        let key_c = gen_alloc.allocate(3)?;
+        gen_alloc.deallocate(&key_c)?;
+
+        let (first, second) = gen_alloc
+            .get_pair_mut_by_index(1, 0)
+            .expect("Distinct indices in range");
+        assert_eq!((*first, *second), (Some(2), Some(1)));
+        std::mem::swap(first, second);
+        assert_eq!(gen_alloc.get(&key_a), Some(&2));
+        assert_eq!(gen_alloc.get(&key_b), Some(&1));
+
+        let (occupied, vacant) = gen_alloc
+            .get_pair_mut_by_index(0, 2)
+            .expect("Vacant slots are accessible as well");
+        assert_eq!((*occupied, *vacant), (Some(2), None));
+
+        assert!(gen_alloc.get_pair_mut_by_index(1, 1).is_none());
+        assert!(gen_alloc.get_pair_mut_by_index(0, 3).is_none());
+        assert!(gen_alloc.get_pair_mut_by_index(3, 0).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_keys_where() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(8);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[2])?;
+
+        let even = gen_alloc.keys_where(|_, value| value % 2 == 0);
+        assert_eq!(even, vec![keys[0], keys[4]]);
+        assert!(gen_alloc.keys_where(|_, _| false).is_empty());
+
+        for key in even.iter() {
+            *gen_alloc.get_mut(key).expect("Key should be valid") += 10;
+        }
+        assert_eq!(gen_alloc.get(&keys[4]), Some(&14));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_reserve_contiguous() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(8);
+        let first = gen_alloc.allocate(0)?;
+        gen_alloc.allocate(1)?;
+        gen_alloc.deallocate(&first)?;
+
+        let keys = gen_alloc.reserve_contiguous(3)?;
+        let indices: Vec<_> = keys.iter().map(|key| key.index()).collect();
+        assert_eq!(indices, vec![2, 3, 4], "Free slot 0 is not reused");
+        assert_eq!(gen_alloc.free_indices, vec![0]);
+        assert_eq!(gen_alloc.len(), 1);
+
+        for (value, key) in keys.iter().enumerate() {
+            assert!(gen_alloc.get(key).is_none());
+            gen_alloc.put(key, value + 10)?;
+        }
+        for (value, key) in keys.iter().enumerate() {
+            assert_eq!(gen_alloc.get(key), Some(&(value + 10)));
+        }
+        assert_eq!(gen_alloc.len(), 4);
+
+        assert!(gen_alloc.reserve_contiguous(0)?.is_empty());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_release_reserved() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(8);
+        let keys = gen_alloc.reserve_contiguous(3)?;
+        gen_alloc.put(&keys[0], 0)?;
+
+        gen_alloc.release_reserved(&keys[1])?;
+        assert_ne!(
+            gen_alloc.generation_at(keys[1].index()),
+            Some(keys[1].generation)
+        );
+        assert_eq!(gen_alloc.free_indices, vec![keys[1].index()]);
+        assert!(gen_alloc.put(&keys[1], 1).is_err(), "Released key is stale");
+
+        assert!(gen_alloc.release_reserved(&keys[0]).is_err(), "Occupied");
+        assert!(gen_alloc.release_reserved(&keys[1]).is_err(), "Stale");
+        let free_key = GenIndex {
+            index: keys[1].index(),
+            generation: keys[1].generation + 1,
+        };
+        assert!(
+            gen_alloc.release_reserved(&free_key).is_err(),
+            "Already free"
+        );
+
+        gen_alloc.force_generation(keys[2].index(), u32::MAX);
+        let retired_key = GenIndex {
+            index: keys[2].index(),
+            generation: u32::MAX,
+        };
+        assert!(gen_alloc.release_reserved(&retired_key).is_err(), "Retired");
+
+        // The released slot is reused
+        let key = gen_alloc.allocate(4)?;
+        assert_eq!(key.index(), keys[1].index());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_entry() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let key = gen_alloc.allocate(1)?;
+        let removed = gen_alloc.allocate(2)?;
+        gen_alloc.deallocate(&removed)?;
+
+        assert_eq!(gen_alloc.get_entry(&key), Some((key, &1)));
+        assert_eq!(gen_alloc.get_entry(&removed), None);
+        assert_eq!(
+            gen_alloc.get_entry(&GenIndex::new(0, key.generation() + 1)),
+            None
+        );
+        assert_eq!(gen_alloc.get_entry(&GenIndex::new(5, 0)), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_extend_exact() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let live = gen_alloc.allocate(0)?;
+        let removed = gen_alloc.allocate(1)?;
+        gen_alloc.deallocate(&removed)?;
+
+        let exact = [GenIndex::new(1, 7), GenIndex::new(4, 3)];
+        gen_alloc.extend_exact(exact.iter().copied().zip([11, 14]))?;
+        assert_eq!(gen_alloc.get(&exact[0]), Some(&11));
+        assert_eq!(gen_alloc.get(&exact[1]), Some(&14));
+        assert_eq!(gen_alloc.get(&live), Some(&0));
+        assert_eq!(gen_alloc.len(), 3);
+
+        // Padding slots are free, exact slots are not
+        let mut free = gen_alloc.free_indices.clone();
+        free.sort_unstable();
+        assert_eq!(free, vec![2, 3]);
+
+        // Collisions leave the allocator untouched
+        assert!(gen_alloc
+            .extend_exact([(GenIndex::new(2, 0), 2), (GenIndex::new(0, 5), 0)])
+            .is_err());
+        assert!(gen_alloc
+            .extend_exact([(GenIndex::new(2, 0), 2), (GenIndex::new(2, 1), 2)])
+            .is_err());
+        assert_eq!(gen_alloc.len(), 3);
+        assert_eq!(gen_alloc.value_at(2), None);
+
+        // Stale keys of vacant slots stay invalid
+        let stale = gen_alloc.allocate(2)?;
+        gen_alloc.deallocate(&stale)?;
+        assert!(gen_alloc.extend_exact([(stale, 99)]).is_err());
+        assert_eq!(gen_alloc.get(&stale), None);
+
+        // A retired slot cannot take a value
+        gen_alloc.force_generation(live.index(), u32::MAX);
+        let retired = gen_alloc.current_key_at(live.index()).unwrap();
+        gen_alloc.deallocate(&retired)?;
+        assert!(gen_alloc.extend_exact([(retired, 5)]).is_err());
+        assert_eq!(gen_alloc.value_at(live.index()), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_with_index_hint() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_index_hint(2);
+        let keys: Vec<_> = (0..3)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+
+        let err = gen_alloc.allocate(3).expect_err("Index 3 is above the cap");
+        assert_eq!(
+            err.downcast_ref::<IndexTooLarge>(),
+            Some(&IndexTooLarge {
+                index: 3,
+                max_index: 2
+            })
+        );
+        assert_eq!(gen_alloc.len(), 3);
+        assert_eq!(gen_alloc.slots.len(), 3);
+
+        // Free slots below the cap are still reused
+        gen_alloc.deallocate(&keys[1])?;
+        assert_eq!(gen_alloc.allocate(4)?.index(), 1);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_index_hint_in_all_slot_creating_methods() -> Result<(), Error> {
+        let is_too_large = |err: Error| err.downcast_ref::<IndexTooLarge>().is_some();
+        let mut gen_alloc = GenIndexAllocator::with_index_hint(3);
+        gen_alloc.allocate(0)?;
+        gen_alloc.allocate(1)?;
+
+        assert!(is_too_large(gen_alloc.ensure_index(4).unwrap_err()));
+        assert!(is_too_large(gen_alloc.get_or_spawn(4, || 4).unwrap_err()));
+        assert!(is_too_large(gen_alloc.reserve_contiguous(3).unwrap_err()));
+        assert!(is_too_large(
+            gen_alloc.allocate_slice(&[2, 3, 4]).unwrap_err()
+        ));
+        let too_large = GenIndex {
+            index: 4,
+            generation: 0,
+        };
+        assert!(is_too_large(
+            gen_alloc.extend_exact([(too_large, 4)]).unwrap_err()
+        ));
+        assert_eq!(gen_alloc.slots.len(), 2, "Failed calls create no slots");
+
+        assert_eq!(gen_alloc.reserve_contiguous(1)?.len(), 1);
+        assert_eq!(gen_alloc.allocate_slice(&[3])?.len(), 1);
+        assert!(is_too_large(gen_alloc.begin_reserve().err().unwrap()));
+        assert_eq!(gen_alloc.slots.len(), 4);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_round_trip() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let keys: Vec<_> = (0..3)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+        gen_alloc.set_overflow_policy(OverflowPolicy::Wrap);
+
+        let json = serde_json::to_string(&gen_alloc)?;
+        let mut loaded: GenIndexAllocator<i32> = serde_json::from_str(&json)?;
+        assert_eq!(loaded.len(), 2);
+        assert_eq!(loaded.get(&keys[0]), Some(&0));
+        assert_eq!(loaded.get(&keys[1]), None);
+        assert_eq!(loaded.get(&keys[2]), Some(&2));
+        assert_eq!(loaded.overflow_policy(), OverflowPolicy::Wrap);
+
+        let reused = loaded.allocate(3)?;
+        assert_eq!(reused.index(), 1);
+        assert_ne!(reused, keys[1]);
+
+        Ok(())
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde_rejects_corrupt_data() {
+        let load = |json: &str| serde_json::from_str::<GenIndexAllocator<i32>>(json);
+        let payload = |values: &str, free_indices: &str| {
+            format!(
+                r#"{{"generations":[1,1],"values":{},"free_indices":{},"overflow_policy":"Retire","generation_floor":0}}"#,
+                values, free_indices
+            )
+        };
+        let values = "[5, null]";
+
+        assert!(load(&payload(values, "[1]")).is_ok());
+
+        let err = load(&payload(values, "[1, 1]"))
+            .err()
+            .expect("Duplicate free index");
+        assert!(err.to_string().contains("listed twice"));
+        let err = load(&payload(values, "[0, 1]"))
+            .err()
+            .expect("Free index of occupied slot");
+        assert!(err.to_string().contains("occupied slot"));
+        let err = load(&payload(values, "[2]"))
+            .err()
+            .expect("Free index out of range");
+        assert!(err.to_string().contains("out of range"));
+
+        let err = load(&payload("[5]", "[]"))
+            .err()
+            .expect("Values do not match generations");
+        assert!(err.to_string().contains("2 generations but 1 values"));
+    }
+
+    #[test]
+    fn test_max_live_generation() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        assert_eq!(gen_alloc.max_live_generation(), None);
+
+        let first = gen_alloc.allocate(0)?;
+        let mut second = gen_alloc.allocate(1)?;
+        assert_eq!(gen_alloc.max_live_generation(), Some(0));
+
+        for value in 0..3 {
+            gen_alloc.deallocate(&second)?;
+            second = gen_alloc.allocate(value)?;
+        }
+        assert_eq!(second.generation(), 3);
+        assert_eq!(gen_alloc.max_live_generation(), Some(3));
+
+        // Vacant slots do not count, even with a higher generation
+        gen_alloc.deallocate(&second)?;
+        assert_eq!(gen_alloc.generation_at(1), Some(4));
+        assert_eq!(gen_alloc.max_live_generation(), Some(0));
+
+        gen_alloc.deallocate(&first)?;
+        assert_eq!(gen_alloc.max_live_generation(), None);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_mut_pair() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let a = gen_alloc.allocate(1)?;
+        let b = gen_alloc.allocate(2)?;
+        let removed = gen_alloc.allocate(3)?;
+        gen_alloc.deallocate(&removed)?;
+
+        let (first, second) = gen_alloc.get_mut_pair(&b, &a)?;
+        assert_eq!((*first, *second), (2, 1));
+        *first += 10;
+        *second += 20;
+        assert_eq!(gen_alloc.get(&a), Some(&21));
+        assert_eq!(gen_alloc.get(&b), Some(&12));
+
+        assert_eq!(
+            gen_alloc.get_mut_pair(&a, &a).err(),
+            Some(PairError::SameEntity)
+        );
+        assert_eq!(
+            gen_alloc.get_mut_pair(&removed, &a).err(),
+            Some(PairError::FirstInvalid)
+        );
+        assert_eq!(
+            gen_alloc.get_mut_pair(&a, &GenIndex::new(7, 0)).err(),
+            Some(PairError::SecondInvalid)
+        );
+        assert_eq!(
+            gen_alloc.get_mut_pair(&removed, &removed).err(),
+            Some(PairError::FirstInvalid)
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_changes_since() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let untracked = gen_alloc.allocate(0)?;
+        gen_alloc.enable_change_log(8);
+
+        let mut cursor = ChangeCursor::default();
+        let a = gen_alloc.allocate(1)?;
+        let b = gen_alloc.allocate(2)?;
+        gen_alloc.set(&a, 10)?;
+        let changes: Vec<_> = gen_alloc.changes_since(&mut cursor).collect();
+        assert_eq!(
+            changes,
+            vec![
+                (a, ChangeKind::Added),
+                (b, ChangeKind::Added),
+                (a, ChangeKind::Modified)
+            ]
+        );
+
+        assert_eq!(gen_alloc.changes_since(&mut cursor).count(), 0);
+
+        gen_alloc.deallocate(&untracked)?;
+        *gen_alloc.get_mut(&b).expect("Key should be valid") = 20;
+        let c = gen_alloc.allocate(3)?;
+        let changes: Vec<_> = gen_alloc.changes_since(&mut cursor).collect();
+        assert_eq!(
+            changes,
+            vec![(untracked, ChangeKind::Removed), (c, ChangeKind::Added)]
+        );
+
+        // A second cursor from the start sees everything still in the log
+        let mut late_cursor = ChangeCursor::default();
+        assert_eq!(gen_alloc.changes_since(&mut late_cursor).count(), 5);
+        assert_eq!(late_cursor, cursor);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_change_log_capacity() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let mut cursor = ChangeCursor::default();
+        assert_eq!(gen_alloc.changes_since(&mut cursor).count(), 0);
+
+        gen_alloc.enable_change_log(2);
+        let keys: Vec<_> = (0..3)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        let changes: Vec<_> = gen_alloc.changes_since(&mut cursor).collect();
+        assert_eq!(
+            changes,
+            vec![(keys[1], ChangeKind::Added), (keys[2], ChangeKind::Added)],
+            "The oldest change is dropped"
+        );
+
+        gen_alloc.disable_change_log();
+        gen_alloc.deallocate(&keys[0])?;
+        assert_eq!(gen_alloc.changes_since(&mut cursor).count(), 0);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_try_reserve_exact() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(0);
+        gen_alloc.allocate(0)?;
+        assert!(gen_alloc.slots.capacity() < 11);
+
+        // The allocator may still give more than requested, see `Vec::try_reserve_exact`
+        gen_alloc.try_reserve_exact(10)?;
+        let capacity = gen_alloc.slots.capacity();
+        assert!(capacity >= 11);
+        for value in 1..11 {
+            gen_alloc.allocate(value)?;
+        }
+        assert_eq!(
+            gen_alloc.slots.capacity(),
+            capacity,
+            "Reserved room is used without reallocating"
+        );
+
+        let mut empty = GenIndexAllocator::<u64>::with_capacity(0);
+        empty.try_reserve_exact(7)?;
+        let capacity = empty.slots.capacity();
+        assert!(capacity >= 7);
+
+        assert!(empty.try_reserve_exact(usize::MAX).is_err());
+        assert_eq!(empty.slots.capacity(), capacity);
+        for value in 0..7 {
+            empty.allocate(value)?;
+        }
+        assert_eq!(empty.slots.capacity(), capacity);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_compaction_plan() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10);
+        let keys: Vec<_> = (0..8)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        for index in [1, 2, 5] {
+            gen_alloc.deallocate(&keys[index])?;
+        }
+        let num_slots = gen_alloc.slots.len();
+
+        let plan = gen_alloc.compaction_plan();
+        assert_eq!(gen_alloc.slots.len(), num_slots, "Planning changes nothing");
+        assert_eq!(gen_alloc.free_indices.len(), 3);
+        assert_eq!(plan.len(), 5);
+        assert_ne!(plan[&keys[7]], keys[7]);
+        assert_eq!(plan[&keys[0]], keys[0]);
+
+        let remap = gen_alloc.compact();
+        assert_eq!(plan, remap);
+        for (old_key, new_key) in plan.iter() {
+            assert_eq!(gen_alloc.get(new_key), Some(&(old_key.index() as i32)));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_mutate_keys() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(8);
+        let keys: Vec<_> = (0..5)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[2])?;
+
+        let targets = [keys[4], keys[2], keys[0], keys[4], keys[3]];
+        let mut visited = Vec::new();
+        let num_applied = gen_alloc.mutate_keys(&targets, |key, value| {
+            visited.push(key.index());
+            *value += 10;
+        });
+        assert_eq!(num_applied, 3);
+        assert_eq!(visited, vec![0, 3, 4]);
+        assert_eq!(gen_alloc.get(&keys[0]), Some(&10));
+        assert_eq!(gen_alloc.get(&keys[1]), Some(&1));
+        assert_eq!(gen_alloc.get(&keys[4]), Some(&14));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_live_key_set_and_covers() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let keys: Vec<_> = (0..4)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+        let reused = gen_alloc.allocate(10)?;
+
+        let live = gen_alloc.live_key_set();
+        let expected: HashSet<_> = [keys[0], reused, keys[2], keys[3]].into_iter().collect();
+        assert_eq!(live, expected);
+
+        assert!(gen_alloc.covers(live.iter().copied()));
+        assert!(gen_alloc.covers(std::iter::empty()));
+        assert!(!gen_alloc.covers([keys[0], keys[1]].into_iter()));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_recompute_occupied_count() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let keys: Vec<_> = (0..3)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[0])?;
+
+        gen_alloc.num_occupied = 7;
+        assert_eq!(gen_alloc.len(), 7);
+
+        assert_eq!(gen_alloc.recompute_occupied_count(), 2);
+        assert_eq!(gen_alloc.len(), 2);
+        assert_eq!(gen_alloc.count(), 2);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_allocate_slice() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(8);
+        let first = gen_alloc.allocate("first".to_string())?;
+        gen_alloc.allocate("second".to_string())?;
+        gen_alloc.deallocate(&first)?;
+
+        let values = ["a".to_string(), "b".to_string(), "c".to_string()];
+        let keys = gen_alloc.allocate_slice(&values)?;
+        let indices: Vec<_> = keys.iter().map(|key| key.index()).collect();
+        assert_eq!(indices, vec![2, 3, 4], "Free slot 0 is not reused");
+        for (key, value) in keys.iter().zip(values.iter()) {
+            assert_eq!(gen_alloc.get(key), Some(value));
+        }
+        assert_eq!(gen_alloc.len(), 4);
+        assert_eq!(gen_alloc.free_indices, vec![0]);
+
+        assert!(gen_alloc.allocate_slice(&[])?.is_empty());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "reuse-log")]
+    #[test]
+    fn test_reuse_log() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let first = gen_alloc.allocate(0)?;
+        let second = gen_alloc.allocate(1)?;
+        assert!(gen_alloc.reuse_log().is_empty());
+
+        gen_alloc.deallocate(&second)?;
+        let reused = gen_alloc.allocate(2)?;
+        gen_alloc.deallocate(&reused)?;
+        gen_alloc.deallocate(&first)?;
+        assert_eq!(gen_alloc.reuse_log(), &[(1, 0, 1), (1, 1, 2), (0, 0, 1)]);
+
+        // Rolled back reservations bump the generation as well
+        drop(gen_alloc.begin_reserve()?);
+        assert_eq!(gen_alloc.reuse_log().last(), Some(&(0, 1, 2)));
+
+        gen_alloc.clear_reuse_log();
+        assert!(gen_alloc.reuse_log().is_empty());
+
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_for_each_mut() -> Result<(), Error> {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+
+        let mut gen_alloc = GenIndexAllocator::with_capacity(1000);
+        let keys: Vec<_> = (0..1000u64)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        for key in keys.iter().step_by(3) {
+            gen_alloc.deallocate(key)?;
+        }
+        let mut sequential = GenIndexAllocator::from_options(gen_alloc.values.clone());
+        sequential.update_all(|key, value| *value = *value * 2 + key.index() as u64);
+
+        let visits = AtomicUsize::new(0);
+        gen_alloc.par_for_each_mut(64, |key, value| {
+            visits.fetch_add(1, Ordering::Relaxed);
+            *value = *value * 2 + key.index() as u64;
+        });
+        assert_eq!(visits.load(Ordering::Relaxed), gen_alloc.len());
+
+        let parallel_sum: u64 = gen_alloc.iter().map(|(_, value)| value).sum();
+        let sequential_sum: u64 = sequential.iter().map(|(_, value)| value).sum();
+        assert_eq!(parallel_sum, sequential_sum);
+        for (key, value) in gen_alloc.iter() {
+            assert_eq!(sequential.value_at(key.index()), Some(value));
+        }
+
+        gen_alloc.par_for_each_mut(0, |_, value| *value = 0);
+        assert!(gen_alloc.iter().all(|(_, value)| *value == 0));
+
+        Ok(())
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_for_each_mut_counts_occupied_entries() -> Result<(), Error> {
+        // 100 live values spread over 10000 slots
+        let mut gen_alloc = GenIndexAllocator::with_capacity(10_000);
+        let keys: Vec<_> = (0..10_000)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        for key in keys.iter().filter(|key| key.index() % 100 != 0) {
+            gen_alloc.deallocate(key)?;
+        }
+
+        // A minimum chunk covering all live values leaves a single chunk, visited in order
+        let visited = Mutex::new(Vec::new());
+        gen_alloc.par_for_each_mut(100, |key, _| visited.lock().unwrap().push(key.index()));
+        let visited = visited.into_inner().unwrap();
+        let expected: Vec<_> = (0..10_000).step_by(100).collect();
+        assert_eq!(visited, expected);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_packed_keys() -> Result<(), Error> {
+        let key = GenIndex::new(3, 7);
+        assert_eq!(key.to_bits(), (7 << 32) | 3);
+        assert_eq!(GenIndex::from_bits(key.to_bits()), key);
+        let max_key = GenIndex::new(u32::MAX as usize, u32::MAX);
+        assert_eq!(GenIndex::from_bits(max_key.to_bits()), max_key);
+
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let keys: Vec<_> = (0..4)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+        let reused = gen_alloc.allocate(10)?;
+
+        let unpacked: Vec<_> = gen_alloc.packed_keys().map(GenIndex::from_bits).collect();
+        assert_eq!(unpacked, vec![keys[0], reused, keys[2], keys[3]]);
+
+        Ok(())
+    }
+
+    #[cfg(target_pointer_width = "64")]
+    #[test]
+    #[should_panic(expected = "Index exceeds 32 bits")]
+    fn test_to_bits_index_too_large() {
+        GenIndex::new(u32::MAX as usize + 1, 0).to_bits();
+    }
+
+    #[test]
+    fn test_deallocate_if() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let key = gen_alloc.allocate(5)?;
+
+        assert_eq!(gen_alloc.deallocate_if(&key, |value| *value > 10)?, None);
+        assert_eq!(gen_alloc.get(&key), Some(&5));
+
+        assert_eq!(gen_alloc.deallocate_if(&key, |value| *value == 5)?, Some(5));
+        assert!(!gen_alloc.contains(&key));
+        assert!(gen_alloc.is_empty());
+
+        assert!(gen_alloc.deallocate_if(&key, |_| true).is_err());
+        assert!(gen_alloc
+            .deallocate_if(&GenIndex::new(3, 0), |_| true)
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_free_list_capacity() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_initial_free_capacity(8);
+        let keys: Vec<_> = (0..8)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        let free_capacity = gen_alloc.free_indices.capacity();
+        assert!(free_capacity >= 8);
+        for key in keys.iter() {
+            gen_alloc.deallocate(key)?;
+        }
+        assert_eq!(gen_alloc.free_indices.capacity(), free_capacity);
+
+        let mut gen_alloc = GenIndexAllocator::with_capacities(20, 0);
+        let keys: Vec<_> = (0..20)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.set_free_growth_chunk(16);
+        gen_alloc.deallocate(&keys[0])?;
+        let chunk_capacity = gen_alloc.free_indices.capacity();
+        assert!(chunk_capacity >= 16);
+        for key in keys[1..16].iter() {
+            gen_alloc.deallocate(key)?;
+        }
+        assert_eq!(
+            gen_alloc.free_indices.capacity(),
+            chunk_capacity,
+            "The first chunk has room for 16 indices"
+        );
+        gen_alloc.deallocate(&keys[16])?;
+        let capacity = gen_alloc.free_indices.capacity();
+        assert!(
+            capacity == chunk_capacity || capacity >= chunk_capacity + 16,
+            "A full free list grows by at least one chunk"
+        );
+
+        gen_alloc.reserve_free(100);
+        assert!(gen_alloc.free_indices.capacity() >= gen_alloc.free_indices.len() + 100);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_remap_external() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(8);
+        let mut by_name = HashMap::new();
+        for name in ["a", "b", "c", "d"] {
+            by_name.insert(name, gen_alloc.allocate(name)?);
+        }
+        gen_alloc.deallocate(&by_name["b"])?;
+
+        let remap = gen_alloc.compact();
+        remap_external(&mut by_name, &remap);
+        assert_eq!(by_name.len(), 3);
+        assert!(!by_name.contains_key("b"));
+        for (name, key) in by_name.iter() {
+            assert_eq!(gen_alloc.get(key), Some(name));
+        }
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_has_no_live_entries() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        assert!(gen_alloc.has_no_live_entries());
+        assert!(gen_alloc.all_slots_vacant());
+
+        for value in 0..3 {
+            gen_alloc.allocate(value)?;
+        }
+        assert!(!gen_alloc.has_no_live_entries());
+        assert!(!gen_alloc.all_slots_vacant());
+
+        gen_alloc.drain_to_vec();
+        assert!(gen_alloc.has_no_live_entries());
+        assert!(gen_alloc.all_slots_vacant());
+        assert_eq!(gen_alloc.slots.len(), 3, "Slots are kept");
+
+        gen_alloc.allocate(4)?;
+        gen_alloc.clear();
+        assert!(gen_alloc.has_no_live_entries());
+        assert!(gen_alloc.all_slots_vacant());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_get_or_spawn() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let removed = gen_alloc.allocate(0)?;
+        gen_alloc.deallocate(&removed)?;
+
+        let spawned = gen_alloc.get_or_spawn(3, || 30)?;
+        assert_eq!(spawned.index(), 3);
+        assert_eq!(gen_alloc.get(&spawned), Some(&30));
+        assert_eq!(gen_alloc.len(), 1);
+
+        let again = gen_alloc.get_or_spawn(3, || panic!("Should not spawn twice"))?;
+        assert_eq!(again, spawned);
+        assert_eq!(gen_alloc.get(&again), Some(&30));
+
+        // A freed slot gets a new generation, the stale key stays invalid
+        let respawned = gen_alloc.get_or_spawn(0, || 10)?;
+        assert_eq!(respawned.index(), 0);
+        assert_ne!(respawned, removed);
+        assert!(!gen_alloc.contains(&removed));
+
+        // Padding slots are free for regular allocations
+        let mut free = gen_alloc.free_indices.clone();
+        free.sort_unstable();
+        assert_eq!(free, vec![1, 2]);
+        assert_eq!(gen_alloc.len(), 2);
+
+        // A retired slot cannot take a value
+        let retired = gen_alloc.get_or_spawn(1, || 20)?;
+        gen_alloc.force_generation(retired.index(), u32::MAX);
+        let retired = gen_alloc.current_key_at(retired.index()).unwrap();
+        gen_alloc.deallocate(&retired)?;
+        assert!(gen_alloc
+            .get_or_spawn(retired.index(), || panic!("Should not spawn"))
+            .is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_swap_remove_return_moved_key() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(8);
+        let keys: Vec<_> = (0..6)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        // Corrupt the free list with stale copies of the indices freed later
+        gen_alloc.free_indices.extend([5, 4, 5]);
+
+        let (removed, moved) = gen_alloc
+            .swap_remove_return_moved_key(0)
+            .expect("Slot 0 is occupied");
+        assert_eq!(removed, 0);
+        let moved = moved.expect("The last entry is moved");
+        assert_eq!(moved.index(), 0);
+        assert_eq!(gen_alloc.get(&moved), Some(&5));
+        assert!(!gen_alloc.contains(&keys[5]));
+        assert_eq!(gen_alloc.free_indices, vec![4, 5]);
+
+        let (removed, moved) = gen_alloc
+            .swap_remove_return_moved_key(1)
+            .expect("Slot 1 is occupied");
+        assert_eq!(removed, 1);
+        let moved = moved.expect("The last entry is moved");
+        assert_eq!(gen_alloc.get(&moved), Some(&4));
+        assert_eq!(gen_alloc.free_indices, vec![5, 4]);
+
+        // Removing the last live entry moves nothing
+        let (removed, moved) = gen_alloc
+            .swap_remove_return_moved_key(3)
+            .expect("Slot 3 is occupied");
+        assert_eq!((removed, moved), (3, None));
+
+        let mut free = gen_alloc.free_indices.clone();
+        free.sort_unstable();
+        free.dedup();
+        assert_eq!(free.len(), gen_alloc.free_indices.len(), "No duplicates");
+        assert_eq!(gen_alloc.len(), 3);
+        assert!(gen_alloc.swap_remove_return_moved_key(5).is_none());
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_to_key_set() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(4);
+        let keys: Vec<_> = (0..4)
+            .map(|value| gen_alloc.allocate(value).expect("Should allocate"))
+            .collect();
+        gen_alloc.deallocate(&keys[1])?;
+        gen_alloc.deallocate(&keys[3])?;
+        let reused = gen_alloc.allocate(10)?;
+
+        let key_set = gen_alloc.to_key_set();
+        assert_eq!(key_set.len(), gen_alloc.len());
+        for key in gen_alloc.keys() {
+            assert!(key_set.contains_key(&key));
+        }
+        assert!(!key_set.contains_key(&keys[1]));
+        assert!(!key_set.contains_key(&keys[3]));
+        assert!(key_set.contains_key(&reused));
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_capacity_growth_hook_sees_all_growth() -> Result<(), Error> {
+        let mut gen_alloc = GenIndexAllocator::with_capacity(0);
+        let growths = Arc::new(Mutex::new(Vec::new()));
+        let hook_growths = Arc::clone(&growths);
+        gen_alloc.set_capacity_growth_hook(move |old_cap, new_cap| {
+            hook_growths.lock().unwrap().push((old_cap, new_cap))
+        });
+        let last_growth = || growths.lock().unwrap().last().copied();
+
+        gen_alloc.ensure_index(9)?;
+        assert_eq!(last_growth(), Some((0, gen_alloc.slots.capacity())));
+
+        let old_cap = gen_alloc.slots.capacity();
+        gen_alloc.extend_exact([(GenIndex::new(old_cap + 5, 0), 1)])?;
+        assert_eq!(last_growth(), Some((old_cap, gen_alloc.slots.capacity())));
+
+        let old_cap = gen_alloc.slots.capacity();
+        gen_alloc.try_reserve_exact(old_cap)?;
+        assert_eq!(last_growth(), Some((old_cap, gen_alloc.slots.capacity())));
+
+        let num_growths = growths.lock().unwrap().len();
+        gen_alloc.try_reserve_exact(1)?;
+        assert_eq!(
+            growths.lock().unwrap().len(),
+            num_growths,
+            "Room left, no growth"
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_drop_reservation_at_generation_ceiling() {
+        for policy in [
+            OverflowPolicy::Panic,
+            OverflowPolicy::Wrap,
+            OverflowPolicy::Error,
+            OverflowPolicy::Retire,
+        ] {
+            let mut gen_alloc = GenIndexAllocator::<i32>::with_capacity(10);
+            gen_alloc.set_overflow_policy(policy);
+            let reservation = gen_alloc.begin_reserve().expect("Should reserve");
+            let index = reservation.index;
+            reservation.allocator.force_generation(index, u32::MAX);
+            drop(reservation);
+
+            if policy == OverflowPolicy::Wrap {
+                assert_eq!(gen_alloc.generation_at(index), Some(0));
+                assert_eq!(gen_alloc.free_indices, vec![index]);
+            } else {
+                assert_eq!(
+                    gen_alloc.generation_at(index),
+                    Some(u32::MAX),
+                    "{:?}",
+                    policy
+                );
+                assert!(gen_alloc.free_indices.is_empty(), "{:?}: Retired", policy);
+            }
+        }
     }
 }